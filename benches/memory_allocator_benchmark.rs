@@ -10,7 +10,7 @@ use std::time::Duration;
 
 use alloc::alloc::GlobalAlloc;
 use alloc::alloc::Layout;
-use buddy_system_allocator::LockedHeap;
+use buddy_system_allocator::{FrameAllocator, Heap, LockedHeap, Magazine};
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use rand::{Rng, SeedableRng};
 
@@ -28,6 +28,44 @@ pub fn small_alloc<const ORDER: usize>(heap: &LockedHeap<ORDER>) {
     }
 }
 
+/// Alloc and free objects of a single size on a private heap, which always finds a free block of
+/// the exact size class after the first round
+#[inline]
+pub fn single_size(heap: &mut Heap<ORDER>) {
+    let layout = unsafe { Layout::from_size_align_unchecked(SMALL_SIZE, ALIGN) };
+    // keep the buddy of the freed block allocated, so that it is not merged
+    let keep = heap.alloc(layout).unwrap();
+    for _ in 0..100 {
+        let addr = heap.alloc(layout).unwrap();
+        heap.dealloc(black_box(addr), layout);
+    }
+    heap.dealloc(keep, layout);
+}
+
+/// Alloc and free single frames in a tight loop, as for page tables
+#[inline]
+pub fn single_frame(frame: &mut FrameAllocator) {
+    for _ in 0..100 {
+        let start = frame.alloc(1).unwrap();
+        frame.dealloc(black_box(start), 1);
+    }
+}
+
+/// Create a frame allocator in which every other frame is allocated, so that its free list of
+/// single frames is long
+pub fn fragmented_frames(cache: bool) -> FrameAllocator {
+    let mut frame = FrameAllocator::new();
+    frame.add_frame(0, 1 << 16);
+    for _ in 0..1 << 16 {
+        let _ = frame.alloc(1);
+    }
+    for start in (0..1 << 16).step_by(2) {
+        frame.dealloc(start, 1);
+    }
+    frame.set_frame_cache(cache);
+    frame
+}
+
 /// Alloc large object
 #[inline]
 pub fn large_alloc<const ORDER: usize>(heap: &LockedHeap<ORDER>) {
@@ -138,6 +176,126 @@ pub fn thread_test() {
     }
 }
 
+/// Mixed workload of small and large objects on a private heap
+///
+/// Returns the size of the largest block that is still available at the end, which shows how
+/// fragmented the heap became under the chosen fit policy.
+#[inline]
+pub fn mixed_workload(prefer_largest: bool) -> usize {
+    const SPACE_SIZE: usize = 1024 * 1024;
+    const N_OPERATIONS: usize = 2000;
+
+    let space = vec![0usize; SPACE_SIZE / MACHINE_ALIGN];
+    let mut heap = Heap::<ORDER>::new();
+    heap.set_prefer_largest(prefer_largest);
+    unsafe { heap.init(space.as_ptr() as usize, SPACE_SIZE) };
+
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+    let mut live = Vec::with_capacity(N_OPERATIONS);
+    for _ in 0..N_OPERATIONS {
+        if live.is_empty() || rng.gen_bool(0.6) {
+            // mostly small objects, with the occasional large one
+            let size = if rng.gen_bool(0.9) {
+                rng.gen_range(SMALL_SIZE..=256)
+            } else {
+                rng.gen_range(4096..=32768)
+            };
+            let layout = Layout::from_size_align(size, ALIGN).unwrap();
+            if let Ok(addr) = heap.alloc(layout) {
+                live.push((addr, layout));
+            }
+        } else {
+            let (addr, layout) = live.swap_remove(rng.gen_range(0..live.len()));
+            heap.dealloc(addr, layout);
+        }
+    }
+    let max = heap.max_contiguous_alloc();
+    for (addr, layout) in live {
+        heap.dealloc(addr, layout);
+    }
+    max
+}
+
+/// Storm of small objects on a private heap with a few large blocks
+///
+/// Returns the size of the largest block that is still available while all the small objects are
+/// live, which shows whether the highest orders survived the storm.
+#[inline]
+pub fn small_storm(reserved_orders: usize) -> usize {
+    // the largest block is 1M
+    const STORM_ORDER: usize = 21;
+    const SPACE_SIZE: usize = 4 * 1024 * 1024;
+    const N_OBJECTS: usize = 20000;
+
+    let space = vec![0usize; SPACE_SIZE / MACHINE_ALIGN];
+    let mut heap = Heap::<STORM_ORDER>::new();
+    heap.set_reserve_high_orders(reserved_orders);
+    unsafe { heap.init(space.as_ptr() as usize, SPACE_SIZE) };
+
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+    let mut live = Vec::with_capacity(N_OBJECTS);
+    for _ in 0..N_OBJECTS {
+        let layout = Layout::from_size_align(rng.gen_range(SMALL_SIZE..=256), ALIGN).unwrap();
+        if let Ok(addr) = heap.alloc(layout) {
+            live.push((addr, layout));
+        }
+    }
+    let max = heap.max_contiguous_alloc();
+    for (addr, layout) in live {
+        heap.dealloc(addr, layout);
+    }
+    max
+}
+
+/// Free-heavy workload on a private heap: many small objects which are all freed in random order
+///
+/// Most deallocations look for a buddy that is still allocated, so compare the benchmark with and
+/// without the `bitmap` feature to see the cost of searching the free lists.
+#[inline]
+pub fn free_heavy(heap: &mut Heap<ORDER>) {
+    const N_OBJECTS: usize = 4096;
+
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+    let mut live = Vec::with_capacity(N_OBJECTS);
+    for _ in 0..N_OBJECTS {
+        let layout = Layout::from_size_align(rng.gen_range(SMALL_SIZE..=64), ALIGN).unwrap();
+        live.push((heap.alloc(layout).unwrap(), layout));
+    }
+    while !live.is_empty() {
+        let (addr, layout) = live.swap_remove(rng.gen_range(0..live.len()));
+        heap.dealloc(addr, layout);
+    }
+}
+
+/// Multithreads alloc and free small objects on a shared private heap, either straight from the
+/// heap or through a magazine per thread
+#[inline]
+pub fn multi_thread_small(heap: &LockedHeap<ORDER>, magazine: bool) {
+    const N_THREADS: usize = 4;
+    const N_OBJECTS: usize = 2000;
+
+    thread::scope(|scope| {
+        for i in 0..N_THREADS {
+            scope.spawn(move || {
+                let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(i as u64);
+                let mut cache = Magazine::<32>::new();
+                for _ in 0..N_OBJECTS {
+                    let layout =
+                        Layout::from_size_align(rng.gen_range(SMALL_SIZE..=64), ALIGN).unwrap();
+                    if magazine {
+                        let addr = cache.alloc(heap, layout).unwrap();
+                        cache.dealloc(heap, black_box(addr), layout);
+                    } else {
+                        let addr = heap.try_alloc(layout).unwrap();
+                        heap.dealloc_ptr(black_box(addr), layout);
+                    }
+                }
+                cache.flush(heap);
+            });
+        }
+    });
+}
+
 const ORDER: usize = 33;
 const MACHINE_ALIGN: usize = core::mem::size_of::<usize>();
 /// for now 128M is needed
@@ -162,7 +320,7 @@ static HEAP_ALLOCATOR: LockedHeap<ORDER> = LockedHeap::<ORDER>::new();
 /// rather than in `fn main()`. We need `ctor` to do this.
 #[ctor]
 fn init_heap() {
-    let heap_start = unsafe { HEAP.as_ptr() as usize };
+    let heap_start = core::ptr::addr_of!(HEAP) as usize;
     unsafe {
         HEAP_ALLOCATOR
             .lock()
@@ -182,7 +340,53 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("mutil thread random size", |b| {
         b.iter(|| mutil_thread_random_size(black_box(&HEAP_ALLOCATOR)))
     });
-    c.bench_function("threadtest", |b| b.iter(|| thread_test()));
+    c.bench_function("threadtest", |b| b.iter(thread_test));
+    c.bench_function("mixed workload smallest fit", |b| {
+        b.iter(|| mixed_workload(black_box(false)))
+    });
+    c.bench_function("mixed workload largest fit", |b| {
+        b.iter(|| mixed_workload(black_box(true)))
+    });
+    c.bench_function("small storm", |b| b.iter(|| small_storm(black_box(0))));
+
+    let free_heavy_size = 1024 * 1024;
+    let space = vec![0usize; free_heavy_size / MACHINE_ALIGN].leak();
+    let start = space.as_ptr() as usize;
+    // blocks of at least two words, which a bitmap needs for the links of the free lists
+    let mut heap = Heap::<ORDER>::with_min_order((2 * MACHINE_ALIGN).trailing_zeros() as usize);
+    unsafe { heap.init(start, free_heavy_size) };
+    #[cfg(feature = "bitmap")]
+    {
+        let end = start + free_heavy_size;
+        let bitmap = vec![0usize; Heap::<ORDER>::bitmap_words(start, end)].leak();
+        heap.set_bitmap(bitmap, start, end);
+    }
+    c.bench_function("free heavy", |b| {
+        b.iter(|| free_heavy(black_box(&mut heap)))
+    });
+    c.bench_function("single size", |b| {
+        b.iter(|| single_size(black_box(&mut heap)))
+    });
+    c.bench_function("small storm with reserved top order", |b| {
+        b.iter(|| small_storm(black_box(1)))
+    });
+
+    let mut frame = fragmented_frames(false);
+    c.bench_function("single frame", |b| {
+        b.iter(|| single_frame(black_box(&mut frame)))
+    });
+    let mut frame = fragmented_frames(true);
+    c.bench_function("single frame with cache", |b| {
+        b.iter(|| single_frame(black_box(&mut frame)))
+    });
+
+    let small_heap = LockedHeap::<ORDER>::from_slice(vec![0u8; 1024 * 1024].leak());
+    c.bench_function("multi thread small", |b| {
+        b.iter(|| multi_thread_small(black_box(&small_heap), false))
+    });
+    c.bench_function("multi thread small with magazines", |b| {
+        b.iter(|| multi_thread_small(black_box(&small_heap), true))
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -0,0 +1,114 @@
+//! Provide the bitmap of free blocks of a heap, for the `bitmap` feature
+
+use core::mem::size_of;
+
+/// Smallest order of a heap block, as every block holds at least a `usize`
+const MIN_ORDER: usize = size_of::<usize>().trailing_zeros() as usize;
+
+/// One bit for every possible block of every order in the range [start, end), set when the block is
+/// in a free list
+///
+/// Blocks which are not completely inside the range are not tracked.
+pub(crate) struct Bitmap<const ORDER: usize> {
+    bits: &'static mut [usize],
+    start: usize,
+    end: usize,
+    // index of the first bit of each order
+    offsets: [usize; ORDER],
+}
+
+impl<const ORDER: usize> Bitmap<ORDER> {
+    /// Return the number of bits needed for the range [start, end), and the index of the first bit
+    /// of each order
+    fn layout(start: usize, end: usize) -> (usize, [usize; ORDER]) {
+        let mut offsets = [0; ORDER];
+        let mut bits = 0;
+        for (order, offset) in offsets.iter_mut().enumerate().skip(MIN_ORDER) {
+            *offset = bits;
+            bits += ((end - start) >> order) + 1;
+        }
+        (bits, offsets)
+    }
+
+    /// Return the number of words needed to track the range [start, end)
+    pub(crate) fn words(start: usize, end: usize) -> usize {
+        Self::layout(start, end).0.div_ceil(usize::BITS as usize)
+    }
+
+    /// Create a bitmap with no free block, tracking the range [start, end) in `bits`
+    pub(crate) fn new(bits: &'static mut [usize], start: usize, end: usize) -> Self {
+        assert!(start <= end);
+        assert!(
+            bits.len() >= Self::words(start, end),
+            "a bitmap of {} words is too small for {:#x}..{:#x}",
+            bits.len(),
+            start,
+            end
+        );
+        let (_, offsets) = Self::layout(start, end);
+        bits.fill(0);
+        Bitmap {
+            bits,
+            start,
+            end,
+            offsets,
+        }
+    }
+
+    /// Mark all the blocks as not free
+    pub(crate) fn clear(&mut self) {
+        self.bits.fill(0);
+    }
+
+    /// Return the index of the bit of the block of size 2<sup>order</sup> at `addr`, or `None` if
+    /// the block is not tracked
+    fn index(&self, addr: usize, order: usize) -> Option<usize> {
+        let offset = addr.checked_sub(self.start)?;
+        if order < MIN_ORDER || offset.checked_add(1 << order)? > self.end - self.start {
+            return None;
+        }
+        Some(self.offsets[order] + (offset >> order))
+    }
+
+    /// Return whether the block of size 2<sup>order</sup> at `addr` is free, or `None` if the block
+    /// is not tracked
+    pub(crate) fn get(&self, addr: usize, order: usize) -> Option<bool> {
+        let index = self.index(addr, order)?;
+        let word = usize::BITS as usize;
+        Some(self.bits[index / word] & (1 << (index % word)) != 0)
+    }
+
+    /// Return the number of free blocks of size 2<sup>order</sup>
+    pub(crate) fn count(&self, order: usize) -> usize {
+        if order < MIN_ORDER {
+            return 0;
+        }
+        let word = usize::BITS as usize;
+        let first = self.offsets[order];
+        let last = first + ((self.end - self.start) >> order) + 1;
+        (first / word..last.div_ceil(word))
+            .map(|i| {
+                let mut bits = self.bits[i];
+                if i == first / word {
+                    bits &= !0 << (first % word);
+                }
+                if i == last / word {
+                    bits &= (1 << (last % word)) - 1;
+                }
+                bits.count_ones() as usize
+            })
+            .sum()
+    }
+
+    /// Record whether the block of size 2<sup>order</sup> at `addr` is free, if it is tracked
+    pub(crate) fn set(&mut self, addr: usize, order: usize, free: bool) {
+        if let Some(index) = self.index(addr, order) {
+            let word = usize::BITS as usize;
+            if free {
+                self.bits[index / word] |= 1 << (index % word);
+            } else {
+                self.bits[index / word] &= !(1 << (index % word));
+            }
+        }
+    }
+}
@@ -1,17 +1,26 @@
-use super::prev_power_of_two;
+use crate::util::prev_power_of_two;
+#[cfg(feature = "paranoid")]
+use alloc::collections::BTreeMap;
 use alloc::collections::BTreeSet;
 use core::alloc::Layout;
 use core::cmp::{max, min};
+use core::fmt;
 use core::ops::Range;
 
-#[cfg(feature = "use_spin")]
+#[cfg(any(feature = "use_spin", feature = "use_std_mutex"))]
 use core::ops::Deref;
 #[cfg(feature = "use_spin")]
 use spin::Mutex;
+#[cfg(all(feature = "use_std_mutex", not(feature = "use_spin")))]
+type Mutex<T> = lock_api::Mutex<crate::StdRawMutex, T>;
+
+/// Number of single frames kept by the cache of a frame allocator, see
+/// [`FrameAllocator::set_frame_cache`]
+const FRAME_CACHE_SIZE: usize = 16;
 
 /// A frame allocator that uses buddy system, requiring a global allocator.
 ///
-/// The max order of the allocator is determined by the const generic parameter `ORDER` (`MAX_ORDER = ORDER - 1`). 
+/// The max order of the allocator is determined by the const generic parameter `ORDER` (`MAX_ORDER = ORDER - 1`).
 /// The frame allocator will only be able to allocate ranges of size up to 2<sup>MAX_ORDER</sup>, out of a total
 /// range of size at most 2<sup>MAX_ORDER + 1</sup> - 1.
 ///
@@ -30,10 +39,40 @@ use spin::Mutex;
 /// let num = frame.alloc(2);
 /// assert_eq!(num, Some(0));
 /// ```
+///
+/// `ORDER` defaults to 33, the order used before it became a parameter, so code that names plain
+/// `FrameAllocator` in a type, e.g. of a field or a `static`, keeps compiling with the same max
+/// order. Where no type is written, the order can not be inferred, so code written for earlier
+/// versions as `let frame = FrameAllocator::new();` needs either the type or the order:
+/// ```
+/// use buddy_system_allocator::FrameAllocator;
+/// let frame: FrameAllocator = FrameAllocator::new();
+/// // or
+/// let frame = FrameAllocator::<33>::new();
+/// ```
+///
+/// `ORDER` must be between 1 and the number of bits of `usize`, which is checked at compile time:
+/// ```compile_fail
+/// let frame = buddy_system_allocator::FrameAllocator::<65>::new();
+/// ```
+///
+/// Cloning a frame allocator takes a snapshot of its state. The clone hands out the same frames
+/// as the original, so only one of them should be used to manage the real frames.
+#[derive(Clone)]
 pub struct FrameAllocator<const ORDER: usize = 33> {
     // buddy system with max order of `ORDER - 1`
     free_list: [BTreeSet<usize>; ORDER],
 
+    // size of each allocated range, keyed by its first frame
+    #[cfg(feature = "paranoid")]
+    allocations: BTreeMap<usize, usize>,
+
+    // recently freed single frames, of which only the first `cached` are valid, which are free but
+    // not in `free_list`
+    cache: [usize; FRAME_CACHE_SIZE],
+    cached: usize,
+    cache_enabled: bool,
+
     // statistics
     allocated: usize,
     total: usize,
@@ -42,14 +81,34 @@ pub struct FrameAllocator<const ORDER: usize = 33> {
 impl<const ORDER: usize> FrameAllocator<ORDER> {
     /// Create an empty frame allocator
     pub const fn new() -> Self {
+        const { assert!(ORDER > 0 && ORDER <= usize::BITS as usize) };
         Self {
             free_list: [const { BTreeSet::new() }; ORDER],
+            #[cfg(feature = "paranoid")]
+            allocations: BTreeMap::new(),
+            cache: [0; FRAME_CACHE_SIZE],
+            cached: 0,
+            cache_enabled: false,
             allocated: 0,
             total: 0,
         }
     }
 
+    /// Create an allocator holding all the frames of `ranges`, e.g. the usable ranges of a memory
+    /// map
+    ///
+    /// Each range is added as with [`FrameAllocator::insert`].
+    pub fn from_ranges<I: IntoIterator<Item = Range<usize>>>(ranges: I) -> Self {
+        let mut allocator = Self::new();
+        for range in ranges {
+            allocator.insert(range);
+        }
+        allocator
+    }
+
     /// Add a range of frame number [start, end) to the allocator
+    ///
+    /// An empty range, i.e. `start == end`, adds nothing.
     pub fn add_frame(&mut self, start: usize, end: usize) {
         assert!(start <= end);
 
@@ -57,15 +116,7 @@ impl<const ORDER: usize> FrameAllocator<ORDER> {
         let mut current_start = start;
 
         while current_start < end {
-            let lowbit = if current_start > 0 {
-                current_start & (!current_start + 1)
-            } else {
-                32
-            };
-            let size = min(
-                min(lowbit, prev_power_of_two(end - current_start)),
-                1 << (ORDER - 1),
-            );
+            let size = Self::block_at(current_start, end);
             total += size;
 
             self.free_list[size.trailing_zeros() as usize].insert(current_start);
@@ -73,6 +124,51 @@ impl<const ORDER: usize> FrameAllocator<ORDER> {
         }
 
         self.total += total;
+        self.check_free_frames();
+    }
+
+    /// Return the size of the largest block that starts at `start`, fits before `end`, and is
+    /// aligned to its size, as used to split a range of frames into blocks
+    fn block_at(start: usize, end: usize) -> usize {
+        // frame 0 is aligned to any order
+        let lowbit = if start > 0 {
+            start & (!start + 1)
+        } else {
+            1 << (ORDER - 1)
+        };
+        min(
+            min(lowbit, prev_power_of_two(end - start)),
+            1 << (ORDER - 1),
+        )
+    }
+
+    /// Forget all frames added to the allocator, leaving it as if newly created
+    ///
+    /// Frames allocated before the reset must not be deallocated afterwards.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Keep up to 16 recently freed single frames aside, to hand them out again without searching
+    /// the free lists
+    ///
+    /// This speeds up workloads that allocate and free one frame at a time, e.g. for page tables.
+    /// The cached frames are not merged with their buddies until the cache is flushed, which
+    /// happens when it is disabled and before any allocation that the cache can not serve from
+    /// the free lists alone. The cache is disabled by default.
+    pub fn set_frame_cache(&mut self, enabled: bool) {
+        self.cache_enabled = enabled;
+        if !enabled {
+            self.flush_cache();
+        }
+    }
+
+    /// Put all the cached frames back into the free lists
+    fn flush_cache(&mut self) {
+        while self.cached > 0 {
+            self.cached -= 1;
+            self.free_block(self.cache[self.cached], 0);
+        }
     }
 
     /// Add a range of frames to the allocator.
@@ -82,15 +178,48 @@ impl<const ORDER: usize> FrameAllocator<ORDER> {
 
     /// Allocate a range of frames from the allocator, returning the first frame of the allocated
     /// range.
+    ///
+    /// A `count` of 0 allocates nothing and returns `None`.
+    #[must_use = "the frames are leaked if they are not deallocated"]
     pub fn alloc(&mut self, count: usize) -> Option<usize> {
-        let size = count.next_power_of_two();
+        let size = block_frames(count)?;
         self.alloc_power_of_two(size)
     }
 
+    /// Allocate up to `count` single frames, writing their numbers into `out`, and return how many
+    /// were allocated
+    ///
+    /// Fewer frames are allocated if `out` is shorter than `count` or if the allocator runs out of
+    /// frames. Each frame must be deallocated on its own, with a `count` of 1.
+    pub fn alloc_many(&mut self, count: usize, out: &mut [usize]) -> usize {
+        let mut allocated = 0;
+        for slot in out.iter_mut().take(count) {
+            match self.alloc_power_of_two(1) {
+                Some(frame) => *slot = frame,
+                None => break,
+            }
+            allocated += 1;
+        }
+        allocated
+    }
+
+    /// Allocate a range of frames from the allocator, returning the range of exactly `count`
+    /// frames.
+    ///
+    /// The range must be deallocated with [`FrameAllocator::dealloc`] and the same `count`.
+    #[must_use = "the frames are leaked if they are not deallocated"]
+    pub fn alloc_contiguous(&mut self, count: usize) -> Option<Range<usize>> {
+        self.alloc(count).map(|start| start..start + count)
+    }
+
     /// Allocate a range of frames with the given size and alignment from the allocator, returning
     /// the first frame of the allocated range.
     /// The allocated size is the maximum of the next power of two of the given size and the
     /// alignment.
+    ///
+    /// For example, `Layout::from_size_align(1, 512)` allocates a frame suitable for mapping a
+    /// 2MiB huge page with 4KiB frames.
+    #[must_use = "the frames are leaked if they are not deallocated"]
     pub fn alloc_aligned(&mut self, layout: Layout) -> Option<usize> {
         let size = max(layout.size().next_power_of_two(), layout.align());
         self.alloc_power_of_two(size)
@@ -99,45 +228,275 @@ impl<const ORDER: usize> FrameAllocator<ORDER> {
     /// Allocate a range of frames of the given size from the allocator. The size must be a power of
     /// two. The allocated range will have alignment equal to the size.
     fn alloc_power_of_two(&mut self, size: usize) -> Option<usize> {
+        if size == 1 && self.cached > 0 {
+            self.cached -= 1;
+            let frame = self.cache[self.cached];
+            #[cfg(feature = "paranoid")]
+            self.allocations.insert(frame, 1);
+            self.allocated += 1;
+            self.check_free_frames();
+            return Some(frame);
+        }
         let class = size.trailing_zeros() as usize;
-        for i in class..self.free_list.len() {
-            // Find the first non-empty size class
-            if !self.free_list[i].is_empty() {
-                // Split buffers
-                for j in (class + 1..i + 1).rev() {
-                    if let Some(block_ref) = self.free_list[j].iter().next() {
-                        let block = *block_ref;
-                        self.free_list[j - 1].insert(block + (1 << (j - 1)));
-                        self.free_list[j - 1].insert(block);
-                        self.free_list[j].remove(&block);
-                    } else {
-                        return None;
-                    }
-                }
+        // `class..ORDER` is empty for sizes above the max order, and every shift below is by less
+        // than `ORDER`, which is at most the number of bits of `usize`
+        // Find the first non-empty size class
+        let i = match (class..self.free_list.len()).find(|&i| !self.free_list[i].is_empty()) {
+            Some(i) => i,
+            // the cached frames may merge into a large enough block
+            None if self.cached > 0 => {
+                self.flush_cache();
+                (class..self.free_list.len()).find(|&i| !self.free_list[i].is_empty())?
+            }
+            None => return None,
+        };
+        self.split_lowest(i, size)
+    }
 
-                let result = self.free_list[class].iter().next();
-                if let Some(result_ref) = result {
-                    let result = *result_ref;
-                    self.free_list[class].remove(&result);
-                    self.allocated += size;
-                    return Some(result);
-                } else {
+    /// Allocate a range of frames like [`FrameAllocator::alloc`], but from the free block with the
+    /// lowest frame number among all the blocks that are large enough
+    ///
+    /// `alloc` takes the lowest block of the smallest size class that fits, which may lie above a
+    /// larger free block. This keeps the low frames dense, at the cost of splitting larger blocks.
+    #[must_use = "the frames are leaked if they are not deallocated"]
+    pub fn alloc_low(&mut self, count: usize) -> Option<usize> {
+        let size = block_frames(count)?;
+        self.flush_cache();
+        let class = size.trailing_zeros() as usize;
+        let (_, i) = (class..self.free_list.len())
+            .filter_map(|i| self.free_list[i].first().map(|&block| (block, i)))
+            .min()?;
+        self.split_lowest(i, size)
+    }
+
+    /// Allocate a range of frames like [`FrameAllocator::alloc`], but from the free block closest to
+    /// the frame `hint`, e.g. to keep related allocations in the same NUMA node
+    ///
+    /// The block is taken from the smallest size class that fits, as with `alloc`, and the distance
+    /// of a block is the number of frames between it and `hint`, which is 0 if it contains `hint`.
+    /// A larger block is split towards `hint`, so the frames returned are the closest ones of the
+    /// block.
+    #[must_use = "the frames are leaked if they are not deallocated"]
+    pub fn alloc_near(&mut self, count: usize, hint: usize) -> Option<usize> {
+        let size = block_frames(count)?;
+        self.flush_cache();
+        let class = size.trailing_zeros() as usize;
+        let i = (class..self.free_list.len()).find(|&i| !self.free_list[i].is_empty())?;
+        let below = self.free_list[i].range(..=hint).next_back();
+        let above = self.free_list[i].range(hint..).next();
+        let distance = |block: usize| {
+            if block > hint {
+                block - hint
+            } else {
+                (hint - block).saturating_sub((1 << i) - 1)
+            }
+        };
+        let block = match (below, above) {
+            (Some(&below), Some(&above)) if distance(above) < distance(below) => above,
+            (Some(&below), _) => below,
+            (None, Some(&above)) => above,
+            (None, None) => return None,
+        };
+
+        // Split the block, keeping the half closer to `hint` and freeing the other one
+        self.free_list[i].remove(&block);
+        let mut block = block;
+        for j in (class..i).rev() {
+            let upper = block + (1 << j);
+            if hint >= upper {
+                self.free_list[j].insert(block);
+                block = upper;
+            } else {
+                self.free_list[j].insert(upper);
+            }
+        }
+        #[cfg(feature = "paranoid")]
+        self.allocations.insert(block, size);
+        self.allocated += size;
+        self.check_free_frames();
+        Some(block)
+    }
+
+    /// Split the lowest free block of class `i` down to `size` frames, and allocate its lowest part
+    ///
+    /// No list between the class of `size` and `i` may hold a block lower than the one split, so
+    /// that the lowest block of each list is always the one just split.
+    fn split_lowest(&mut self, i: usize, size: usize) -> Option<usize> {
+        let class = size.trailing_zeros() as usize;
+        // Split buffers. `free_list[i]` is not empty, and each split inserts two blocks into the
+        // next list of the loop, so no list is empty when its turn comes and no block is left half
+        // split.
+        for j in (class + 1..i + 1).rev() {
+            let block = match self.free_list[j].first() {
+                Some(block) => *block,
+                None => {
+                    debug_assert!(false, "free list of class {} is empty while splitting", j);
                     return None;
                 }
-            }
+            };
+            self.free_list[j - 1].insert(block + (1 << (j - 1)));
+            self.free_list[j - 1].insert(block);
+            self.free_list[j].remove(&block);
         }
-        None
+
+        // The split above always leaves a block in `class`
+        let result = match self.free_list[class].first() {
+            Some(result) => *result,
+            None => {
+                debug_assert!(
+                    false,
+                    "free list of class {} is empty after splitting",
+                    class
+                );
+                return None;
+            }
+        };
+        self.free_list[class].remove(&result);
+        #[cfg(feature = "paranoid")]
+        self.allocations.insert(result, size);
+        self.allocated += size;
+        self.check_free_frames();
+        Some(result)
     }
 
     /// Deallocate a range of frames [frame, frame+count) from the frame allocator.
     ///
-    /// The range should be exactly the same when it was allocated, as in heap allocator
+    /// The range should be exactly the same when it was allocated, as in heap allocator. Passing a
+    /// `count` which rounds up to a different power of two corrupts the allocator; with the
+    /// `paranoid` feature enabled, this panics instead.
+    ///
+    /// A `count` of 0 deallocates nothing, like the allocation of 0 frames.
     pub fn dealloc(&mut self, start_frame: usize, count: usize) {
+        if count == 0 {
+            return;
+        }
         let size = count.next_power_of_two();
         self.dealloc_power_of_two(start_frame, size)
     }
 
-    /// Deallocate a range of frames which was previously allocated by [`alloc_aligned`].
+    /// Deallocate a range of frames [frame, frame+count) like [`FrameAllocator::dealloc`], unless
+    /// some of the frames are already free
+    ///
+    /// Returns `true` if the frames were deallocated, and `false` if they were left alone because
+    /// they can not be an allocated block, e.g. when deallocating the same frames twice. Frames that
+    /// were never added to the allocator are not detected. A `count` of 0 is never allocated, so
+    /// it returns `false`.
+    pub fn dealloc_checked(&mut self, start_frame: usize, count: usize) -> bool {
+        let size = match block_frames(count) {
+            Some(size) => size,
+            None => return false,
+        };
+        let class = size.trailing_zeros() as usize;
+        if class >= ORDER || start_frame & (size - 1) != 0 {
+            return false;
+        }
+        let end_frame = match start_frame.checked_add(size) {
+            Some(end_frame) => end_frame,
+            None => return false,
+        };
+
+        // Look for a free block containing the range, or contained in it
+        let free = self.free_list.iter().enumerate().any(|(order, blocks)| {
+            if order >= class {
+                blocks.contains(&(start_frame & !((1 << order) - 1)))
+            } else {
+                blocks.range(start_frame..end_frame).next().is_some()
+            }
+        });
+        let cached = self.cache[..self.cached]
+            .iter()
+            .any(|frame| (start_frame..end_frame).contains(frame));
+        if free || cached {
+            return false;
+        }
+        self.dealloc_power_of_two(start_frame, size);
+        true
+    }
+
+    /// Remove the frames of `range` from the allocator, e.g. to keep frames holding the kernel image
+    /// from being allocated
+    ///
+    /// This is the inverse of [`FrameAllocator::add_frame`]: the free blocks overlapping the range are
+    /// split so that only the frames of the range are removed, and they no longer count in
+    /// [`FrameAllocator::total_frames`]. Returns `false` and leaves the allocator unchanged if any
+    /// frame of the range is not free, i.e. if it is allocated or was never added.
+    pub fn reserve(&mut self, range: Range<usize>) -> bool {
+        assert!(range.start <= range.end);
+        self.flush_cache();
+
+        // check all the frames before changing anything
+        let mut current = range.start;
+        while current < range.end {
+            match self.free_block_containing(current) {
+                Some((start, order)) => current = start + (1 << order),
+                None => return false,
+            }
+        }
+
+        let mut current = range.start;
+        while current < range.end {
+            let (start, order) = self.free_block_containing(current).unwrap();
+            let end = start + (1 << order);
+            self.free_list[order].remove(&start);
+            // put back the frames of the block on either side of the range, which are already split
+            // from their buddies
+            for (mut piece, piece_end) in [(start, range.start), (range.end, end)] {
+                while piece < piece_end {
+                    let size = Self::block_at(piece, piece_end);
+                    self.free_list[size.trailing_zeros() as usize].insert(piece);
+                    piece += size;
+                }
+            }
+            current = end;
+        }
+
+        self.total -= range.len();
+        self.check_free_frames();
+        true
+    }
+
+    /// Deallocate all the frames of `range` at once, which may span several allocations
+    ///
+    /// The range is split into blocks the same way as [`FrameAllocator::add_frame`] does, and each
+    /// of them is merged with its free buddies. Every frame of the range must be allocated, e.g. by
+    /// [`FrameAllocator::alloc_many`], and no allocation may cross the ends of the range; with the
+    /// `paranoid` feature enabled, this panics otherwise.
+    pub fn dealloc_range(&mut self, range: Range<usize>) {
+        assert!(range.start <= range.end);
+
+        #[cfg(feature = "paranoid")]
+        {
+            let mut frames = 0;
+            for (&start, &size) in self.allocations.range(range.clone()) {
+                assert!(
+                    start + size <= range.end,
+                    "frames {:#x}..{:#x} cross the end of {:#x?}",
+                    start,
+                    start + size,
+                    range
+                );
+                frames += size;
+            }
+            assert_eq!(
+                frames,
+                range.len(),
+                "frames of {:#x?} are not all allocated",
+                range
+            );
+            self.allocations.retain(|start, _| !range.contains(start));
+        }
+
+        let mut current_start = range.start;
+        while current_start < range.end {
+            let size = Self::block_at(current_start, range.end);
+            self.free_block(current_start, size.trailing_zeros() as usize);
+            self.allocated -= size;
+            current_start += size;
+        }
+        self.check_free_frames();
+    }
+
+    /// Deallocate a range of frames which was previously allocated by [`FrameAllocator::alloc_aligned`].
     ///
     /// The layout must be exactly the same as when it was allocated.
     pub fn dealloc_aligned(&mut self, start_frame: usize, layout: Layout) {
@@ -145,27 +504,228 @@ impl<const ORDER: usize> FrameAllocator<ORDER> {
         self.dealloc_power_of_two(start_frame, size)
     }
 
+    /// Allocate a range of frames from the allocator, returning a handle which remembers the size
+    /// actually allocated.
+    #[must_use = "the frames are leaked if they are not deallocated"]
+    pub fn alloc_handle(&mut self, count: usize) -> Option<FrameHandle> {
+        let size = block_frames(count)?;
+        self.alloc_power_of_two(size)
+            .map(|start| FrameHandle { start, size })
+    }
+
+    /// Deallocate a range of frames which was previously allocated by [`alloc_handle`].
+    ///
+    /// [`alloc_handle`]: FrameAllocator::alloc_handle
+    pub fn dealloc_handle(&mut self, handle: FrameHandle) {
+        self.dealloc_power_of_two(handle.start, handle.size)
+    }
+
     /// Deallocate a range of frames with the given size from the allocator. The size must be a
     /// power of two.
     fn dealloc_power_of_two(&mut self, start_frame: usize, size: usize) {
         let class = size.trailing_zeros() as usize;
 
+        #[cfg(feature = "paranoid")]
+        match self.allocations.remove(&start_frame) {
+            Some(allocated) if allocated == size => {}
+            Some(allocated) => panic!(
+                "frame {:#x} was allocated with size {} but deallocated with size {}",
+                start_frame, allocated, size
+            ),
+            None => panic!("frame {:#x} is not allocated", start_frame),
+        }
+
+        if size == 1 && self.cache_enabled && self.cached < FRAME_CACHE_SIZE {
+            self.cache[self.cached] = start_frame;
+            self.cached += 1;
+        } else {
+            self.free_block(start_frame, class);
+        }
+        self.allocated -= size;
+        self.check_free_frames();
+    }
+
+    /// Put the block of 2<sup>class</sup> frames at `start_frame` back into the free lists, merging
+    /// it with its free buddies
+    fn free_block(&mut self, start_frame: usize, class: usize) {
         // Merge free buddy lists
         let mut current_ptr = start_frame;
         let mut current_class = class;
-        while current_class < self.free_list.len() {
-            let buddy = current_ptr ^ (1 << current_class);
+        // The top order has no larger class to merge into, so stop there
+        while current_class + 1 < self.free_list.len() {
+            // `ORDER` is at most the number of bits of `usize`, so this never stops the loop, but
+            // the shift can not overflow even if that bound is lifted
+            let buddy = match 1usize.checked_shl(current_class as u32) {
+                Some(size) => current_ptr ^ size,
+                None => break,
+            };
             if self.free_list[current_class].remove(&buddy) {
                 // Free buddy found
                 current_ptr = min(current_ptr, buddy);
                 current_class += 1;
             } else {
-                self.free_list[current_class].insert(current_ptr);
                 break;
             }
         }
+        self.free_list[current_class].insert(current_ptr);
+    }
 
-        self.allocated -= size;
+    /// Return an iterator over the free blocks of the allocator, as ranges of frame numbers
+    ///
+    /// The ranges never overlap but are not sorted.
+    pub fn free_ranges(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        self.free_list
+            .iter()
+            .enumerate()
+            .flat_map(|(order, blocks)| {
+                blocks.iter().map(move |&start| start..start + (1 << order))
+            })
+            .chain(
+                self.cache[..self.cached]
+                    .iter()
+                    .map(|&frame| frame..frame + 1),
+            )
+    }
+
+    /// Return `true` if `frame` is in one of the free blocks of the allocator
+    pub fn is_free(&self, frame: usize) -> bool {
+        self.free_block_containing(frame).is_some() || self.cache[..self.cached].contains(&frame)
+    }
+
+    /// Return the first frame and the order of the free block containing `frame`, if any
+    fn free_block_containing(&self, frame: usize) -> Option<(usize, usize)> {
+        self.free_list
+            .iter()
+            .enumerate()
+            .map(|(order, blocks)| (frame & !((1 << order) - 1), order, blocks))
+            .find(|(start, _, blocks)| blocks.contains(start))
+            .map(|(start, order, _)| (start, order))
+    }
+
+    /// Return `true` if `frame` is not in any free block of the allocator
+    ///
+    /// This does not tell allocated frames from frames that were never added to the allocator, which
+    /// are not free either.
+    pub fn is_allocated(&self, frame: usize) -> bool {
+        !self.is_free(frame)
+    }
+
+    /// Return the number of frames in the free blocks of the allocator
+    ///
+    /// This adds up the free lists, so it is a consistency check of
+    /// [`FrameAllocator::available_frames`], which it always equals.
+    pub fn free_frames(&self) -> usize {
+        self.free_list
+            .iter()
+            .enumerate()
+            .map(|(order, blocks)| blocks.len() << order)
+            .sum::<usize>()
+            + self.cached
+    }
+
+    /// With the `paranoid` feature enabled, check in debug builds that the free blocks add up to
+    /// the available frames
+    fn check_free_frames(&self) {
+        #[cfg(feature = "paranoid")]
+        debug_assert_eq!(
+            self.free_frames(),
+            self.available_frames(),
+            "free blocks do not add up to the available frames"
+        );
+    }
+
+    /// Return the number of frames that are actually allocated
+    pub fn allocated_frames(&self) -> usize {
+        self.allocated
+    }
+
+    /// Return the total number of frames in the allocator
+    pub fn total_frames(&self) -> usize {
+        self.total
+    }
+
+    /// Return the number of frames that are still available for allocation
+    pub fn available_frames(&self) -> usize {
+        self.total - self.allocated
+    }
+}
+
+/// A range of frames allocated by [`FrameAllocator::alloc_handle`]
+#[must_use = "the frames are leaked unless the handle is passed to `dealloc_handle`"]
+#[derive(Debug, PartialEq, Eq)]
+pub struct FrameHandle {
+    start: usize,
+    size: usize,
+}
+
+impl FrameHandle {
+    /// Return the first frame of the range
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Return the number of frames in the range, which is a power of two
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+/// Return the number of frames of the block for an allocation of `count` frames, or `None` if no
+/// block is allocated for it
+fn block_frames(count: usize) -> Option<usize> {
+    if count == 0 {
+        None
+    } else {
+        // a count with no power of two in `usize` is larger than any block
+        count.checked_next_power_of_two()
+    }
+}
+
+impl<const ORDER: usize> Default for FrameAllocator<ORDER> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const ORDER: usize> fmt::Debug for FrameAllocator<ORDER> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("FrameAllocator")
+            .field("allocated", &self.allocated)
+            .field("total", &self.total)
+            .field("available", &self.available_frames())
+            .field("free_blocks", &FreeBlocks(&self.free_list, self.cached))
+            .finish()
+    }
+}
+
+impl<const ORDER: usize> fmt::Display for FrameAllocator<ORDER> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "allocated {} frames, total {} frames, available {} frames",
+            self.allocated,
+            self.total,
+            self.available_frames()
+        )
+    }
+}
+
+/// The number of free blocks of each non-empty order, displayed as a map, with the number of
+/// cached single frames counted in order 0
+struct FreeBlocks<'a>(&'a [BTreeSet<usize>], usize);
+
+impl fmt::Debug for FreeBlocks<'_> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let cached = |order: usize| if order == 0 { self.1 } else { 0 };
+        fmt.debug_map()
+            .entries(
+                self.0
+                    .iter()
+                    .enumerate()
+                    .map(|(order, blocks)| (order, blocks.len() + cached(order)))
+                    .filter(|&(_, count)| count > 0),
+            )
+            .finish()
     }
 }
 
@@ -177,27 +737,80 @@ impl<const ORDER: usize> FrameAllocator<ORDER> {
 /// ```
 /// use buddy_system_allocator::*;
 /// // Notice that the max order is `ORDER - 1`.
-/// let mut frame = LockedFrameAllocator::<33>::new();
-/// assert!(frame.lock().alloc(1).is_none());
+/// let frame = LockedFrameAllocator::<33>::new();
+/// assert!(frame.alloc(1).is_none());
 ///
-/// frame.lock().add_frame(0, 3);
-/// let num = frame.lock().alloc(1);
+/// frame.add_frame(0, 3);
+/// let num = frame.alloc(1);
 /// assert_eq!(num, Some(2));
+/// // or lock it once for several operations
 /// let num = frame.lock().alloc(2);
 /// assert_eq!(num, Some(0));
 /// ```
-#[cfg(feature = "use_spin")]
+#[cfg(any(feature = "use_spin", feature = "use_std_mutex"))]
 pub struct LockedFrameAllocator<const ORDER: usize = 33>(Mutex<FrameAllocator<ORDER>>);
 
-#[cfg(feature = "use_spin")]
+#[cfg(any(feature = "use_spin", feature = "use_std_mutex"))]
 impl<const ORDER: usize> LockedFrameAllocator<ORDER> {
-    /// Creates an empty heap
-    pub fn new() -> Self {
+    /// Creates an empty frame allocator, which can be used to initialize a `static`
+    pub const fn new() -> Self {
         Self(Mutex::new(FrameAllocator::new()))
     }
+
+    /// Add a range of frame number [start, end) to the allocator
+    pub fn add_frame(&self, start: usize, end: usize) {
+        self.0.lock().add_frame(start, end)
+    }
+
+    /// Add a range of frames to the allocator.
+    pub fn insert(&self, range: Range<usize>) {
+        self.0.lock().insert(range)
+    }
+
+    /// Allocate a range of frames from the allocator, returning the first frame of the allocated
+    /// range.
+    #[must_use = "the frames are leaked if they are not deallocated"]
+    pub fn alloc(&self, count: usize) -> Option<usize> {
+        self.0.lock().alloc(count)
+    }
+
+    /// Allocate a range of frames with the given size and alignment from the allocator, returning
+    /// the first frame of the allocated range.
+    #[must_use = "the frames are leaked if they are not deallocated"]
+    pub fn alloc_aligned(&self, layout: Layout) -> Option<usize> {
+        self.0.lock().alloc_aligned(layout)
+    }
+
+    /// Allocate up to `count` single frames into `out` while holding the lock once, and return how
+    /// many were allocated
+    pub fn alloc_many(&self, count: usize, out: &mut [usize]) -> usize {
+        self.0.lock().alloc_many(count, out)
+    }
+
+    /// Deallocate a range of frames [frame, frame+count) from the frame allocator.
+    pub fn dealloc(&self, start_frame: usize, count: usize) {
+        self.0.lock().dealloc(start_frame, count)
+    }
+
+    /// Remove the frames of `range` from the allocator, see [`FrameAllocator::reserve`].
+    pub fn reserve(&self, range: Range<usize>) -> bool {
+        self.0.lock().reserve(range)
+    }
+
+    /// Deallocate all the frames of `range` at once, which may span several allocations.
+    pub fn dealloc_range(&self, range: Range<usize>) {
+        self.0.lock().dealloc_range(range)
+    }
 }
 
-#[cfg(feature = "use_spin")]
+#[cfg(any(feature = "use_spin", feature = "use_std_mutex"))]
+impl<const ORDER: usize> Default for LockedFrameAllocator<ORDER> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(any(feature = "use_spin", feature = "use_std_mutex"))]
 impl<const ORDER: usize> Deref for LockedFrameAllocator<ORDER> {
     type Target = Mutex<FrameAllocator<ORDER>>;
 
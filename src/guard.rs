@@ -0,0 +1,126 @@
+//! Provide allocations that are deallocated when dropped, for single-threaded use of a `Heap`
+
+use crate::{AllocError, Heap};
+use core::alloc::Layout;
+use core::cell::{Ref, RefCell};
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+
+/// A heap which hands out [`Allocation`]s that give their memory back when they are dropped
+///
+/// The guard takes ownership of the heap, and each allocation borrows the guard, so the borrow
+/// checker makes sure that no allocation outlives the heap it came from. The guard is meant for
+/// single-threaded, arena-style use and can not be shared between threads.
+///
+/// # Usage
+///
+/// ```
+/// use buddy_system_allocator::{Heap, HeapGuard};
+/// # use core::alloc::Layout;
+/// let space = [0usize; 64];
+/// let mut heap = Heap::<32>::new();
+/// unsafe { heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(64) as usize) };
+/// let guard = HeapGuard::new(heap);
+/// {
+///     let mut block = guard.allocate(Layout::from_size_align(16, 8).unwrap()).unwrap();
+///     block[0] = 42;
+///     assert_eq!(guard.heap().outstanding_allocations(), 1);
+/// }
+/// assert_eq!(guard.heap().outstanding_allocations(), 0);
+/// ```
+///
+/// The heap can only be taken back once no allocation is left:
+/// ```compile_fail
+/// # use buddy_system_allocator::{Heap, HeapGuard};
+/// # use core::alloc::Layout;
+/// let guard = HeapGuard::new(Heap::<32>::new());
+/// let block = guard.allocate(Layout::from_size_align(16, 8).unwrap());
+/// let heap = guard.into_inner();
+/// drop(block);
+/// ```
+pub struct HeapGuard<const ORDER: usize> {
+    heap: RefCell<Heap<ORDER>>,
+}
+
+impl<const ORDER: usize> HeapGuard<ORDER> {
+    /// Wrap `heap` to hand out allocations which are deallocated on drop
+    pub const fn new(heap: Heap<ORDER>) -> Self {
+        HeapGuard {
+            heap: RefCell::new(heap),
+        }
+    }
+
+    /// Alloc a range of memory satifying `layout` requirements, zeroed so that it can be used as a
+    /// byte slice
+    pub fn allocate(&self, layout: Layout) -> Result<Allocation<'_, ORDER>, AllocError> {
+        let ptr = self.heap.borrow_mut().alloc(layout)?;
+        unsafe { ptr.as_ptr().write_bytes(0, layout.size()) };
+        Ok(Allocation {
+            heap: &self.heap,
+            ptr,
+            layout,
+        })
+    }
+
+    /// Borrow the heap, e.g. to read its statistics
+    ///
+    /// # Panics
+    ///
+    /// Dropping an allocation from this guard while the heap is borrowed panics.
+    pub fn heap(&self) -> Ref<'_, Heap<ORDER>> {
+        self.heap.borrow()
+    }
+
+    /// Return the heap, once all the allocations have been dropped
+    pub fn into_inner(self) -> Heap<ORDER> {
+        self.heap.into_inner()
+    }
+}
+
+impl<const ORDER: usize> From<Heap<ORDER>> for HeapGuard<ORDER> {
+    fn from(heap: Heap<ORDER>) -> Self {
+        Self::new(heap)
+    }
+}
+
+/// A range of memory allocated by [`HeapGuard::allocate`], which is deallocated when dropped
+///
+/// It derefs to a byte slice of the size of its layout.
+pub struct Allocation<'a, const ORDER: usize> {
+    heap: &'a RefCell<Heap<ORDER>>,
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+impl<const ORDER: usize> Allocation<'_, ORDER> {
+    /// Return a pointer to the start of the allocation
+    pub fn as_ptr(&self) -> NonNull<u8> {
+        self.ptr
+    }
+
+    /// Return the layout the memory was allocated with
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+}
+
+impl<const ORDER: usize> Deref for Allocation<'_, ORDER> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // the memory was zeroed when allocated, and this allocation owns it
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl<const ORDER: usize> DerefMut for Allocation<'_, ORDER> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl<const ORDER: usize> Drop for Allocation<'_, ORDER> {
+    fn drop(&mut self) {
+        self.heap.borrow_mut().dealloc(self.ptr, self.layout)
+    }
+}
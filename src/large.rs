@@ -0,0 +1,128 @@
+//! Provide the bookkeeping of large regions, which serve allocations above the max order of a heap
+
+/// Maximum number of large regions of a heap
+const MAX_LARGE_REGIONS: usize = 8;
+
+/// A range of memory [start, end) which holds at most one allocation at a time
+#[derive(Clone, Copy)]
+struct LargeRegion {
+    start: usize,
+    end: usize,
+    used: bool,
+}
+
+impl LargeRegion {
+    /// Return `true` if the region is free and holds `size` bytes aligned to `align`
+    fn fits(&self, size: usize, align: usize) -> bool {
+        !self.used && self.end - self.start >= size && self.start & (align - 1) == 0
+    }
+}
+
+/// A fixed-size set of large regions
+#[derive(Clone, Copy)]
+pub(crate) struct LargeRegions {
+    regions: [LargeRegion; MAX_LARGE_REGIONS],
+    len: usize,
+}
+
+impl LargeRegions {
+    /// Create an empty set of large regions
+    pub(crate) const fn new() -> Self {
+        LargeRegions {
+            regions: [LargeRegion {
+                start: 0,
+                end: 0,
+                used: false,
+            }; MAX_LARGE_REGIONS],
+            len: 0,
+        }
+    }
+
+    /// Add the free range [start, end) to the set
+    pub(crate) fn insert(&mut self, start: usize, end: usize) -> Result<(), &'static str> {
+        if self.len == MAX_LARGE_REGIONS {
+            return Err("too many large regions");
+        }
+        self.regions[self.len] = LargeRegion {
+            start,
+            end,
+            used: false,
+        };
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Return `true` if a free region holds `size` bytes aligned to `align`
+    pub(crate) fn can_alloc(&self, size: usize, align: usize) -> bool {
+        self.regions[..self.len]
+            .iter()
+            .any(|region| region.fits(size, align))
+    }
+
+    /// Mark the smallest free region which holds `size` bytes aligned to `align` as used, and
+    /// return its start and size
+    pub(crate) fn alloc(&mut self, size: usize, align: usize) -> Option<(usize, usize)> {
+        let region = self.regions[..self.len]
+            .iter_mut()
+            .filter(|region| region.fits(size, align))
+            .min_by_key(|region| region.end - region.start)?;
+        region.used = true;
+        Some((region.start, region.end - region.start))
+    }
+
+    /// Mark the used region which starts at `start` as free, and return its size, or `None` if no
+    /// such region is used
+    pub(crate) fn dealloc(&mut self, start: usize) -> Option<usize> {
+        let region = self.regions[..self.len]
+            .iter_mut()
+            .find(|region| region.used && region.start == start)?;
+        region.used = false;
+        Some(region.end - region.start)
+    }
+
+    /// Return the size of the used region which starts at `start`, or `None` if no such region is
+    /// used
+    #[cfg(feature = "allocator_api")]
+    pub(crate) fn used_size(&self, start: usize) -> Option<usize> {
+        self.regions[..self.len]
+            .iter()
+            .find(|region| region.used && region.start == start)
+            .map(|region| region.end - region.start)
+    }
+
+    /// Return `true` if [start, end) shares at least one address with one of the regions
+    #[cfg(feature = "paranoid")]
+    pub(crate) fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.regions[..self.len]
+            .iter()
+            .any(|region| region.start < end && start < region.end)
+    }
+
+    /// Return the size of the largest region, free or not
+    pub(crate) fn max_size(&self) -> usize {
+        self.regions[..self.len]
+            .iter()
+            .map(|region| region.end - region.start)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Return the size of the largest free region
+    pub(crate) fn max_free(&self) -> usize {
+        self.regions[..self.len]
+            .iter()
+            .filter(|region| !region.used)
+            .map(|region| region.end - region.start)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Return the number of bytes in the free regions
+    pub(crate) fn free_bytes(&self) -> usize {
+        self.regions[..self.len]
+            .iter()
+            .filter(|region| !region.used)
+            .map(|region| region.end - region.start)
+            .sum()
+    }
+}
@@ -1,8 +1,17 @@
 #![no_std]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
 
 #[cfg(test)]
 #[macro_use]
 extern crate std;
+#[cfg(all(feature = "use_std_mutex", not(test)))]
+extern crate std;
+
+#[cfg(all(feature = "use_spin", feature = "use_std_mutex"))]
+compile_error!(
+    "the `use_spin` and `use_std_mutex` features select different locks, enable only one of them \
+    (disable the default features to use `use_std_mutex`)"
+);
 
 #[cfg(feature = "use_spin")]
 extern crate spin;
@@ -10,29 +19,82 @@ extern crate spin;
 #[cfg(feature = "alloc")]
 extern crate alloc;
 
-#[cfg(feature = "use_spin")]
+#[cfg(all(feature = "allocator_api", feature = "lock_api"))]
+use core::alloc::Allocator;
+#[cfg(feature = "lock_api")]
 use core::alloc::GlobalAlloc;
 use core::alloc::Layout;
 use core::cmp::{max, min};
 use core::fmt;
 use core::mem::size_of;
-#[cfg(feature = "use_spin")]
+#[cfg(feature = "lock_api")]
 use core::ops::Deref;
 use core::ptr::NonNull;
+#[cfg(feature = "lock_api")]
+use lock_api::RawMutex;
 #[cfg(feature = "use_spin")]
 use spin::Mutex;
+#[cfg(all(feature = "use_std_mutex", not(feature = "use_spin")))]
+type Mutex<T> = lock_api::Mutex<StdRawMutex, T>;
+use util::prev_power_of_two;
+
+/// The lock of a [`LockedHeap`] when none is given
+#[cfg(feature = "use_spin")]
+type DefaultMutex = spin::Mutex<()>;
+#[cfg(all(feature = "use_std_mutex", not(feature = "use_spin")))]
+type DefaultMutex = StdRawMutex;
+// Not a lock, so that the lock of a heap must be given
+#[cfg(all(
+    feature = "lock_api",
+    not(feature = "use_spin"),
+    not(feature = "use_std_mutex")
+))]
+type DefaultMutex = ();
 
+#[cfg(feature = "bitmap")]
+mod bitmap;
 #[cfg(feature = "alloc")]
 mod frame;
+mod guard;
+mod large;
 pub mod linked_list;
+#[cfg(feature = "lock_api")]
+mod magazine;
+mod region;
+mod stats;
+#[cfg(feature = "use_std_mutex")]
+mod std_mutex;
 #[cfg(test)]
 mod test;
+#[cfg(feature = "trace")]
+mod trace;
+pub mod util;
 
 #[cfg(feature = "alloc")]
 pub use frame::*;
+pub use guard::{Allocation, HeapGuard};
+#[cfg(feature = "lock_api")]
+pub use magazine::Magazine;
+#[cfg(feature = "use_std_mutex")]
+pub use std_mutex::StdRawMutex;
 
 /// A heap that uses buddy system with configurable order.
 ///
+/// Blocks never grow beyond 2<sup>ORDER - 1</sup> bytes, so larger regions are kept as many
+/// top-order blocks that can not be coalesced any further. Pick an `ORDER` large enough for the
+/// biggest allocation you need, e.g. `ORDER = 48` to manage multi-gigabyte blocks.
+///
+/// `ORDER` must be between 1 and the number of bits of `usize`, otherwise block sizes would not fit
+/// in a `usize`. Other values are rejected at compile time:
+/// ```compile_fail
+/// let heap = buddy_system_allocator::Heap::<65>::new();
+/// ```
+///
+/// With the `trace` feature, up to `TRACE` outstanding allocations are recorded for
+/// `Heap::leaks`. The parameter has no effect without the feature. The heaps wrapped by this
+/// crate, in `LockedHeap`, `LockedHeapWithRescue` and [`HeapGuard`], have the default `TRACE` of
+/// 256.
+///
 /// # Usage
 ///
 /// Create a heap and add a memory region to it:
@@ -45,32 +107,133 @@ pub use frame::*;
 /// let mut heap = Heap::<33>::empty();
 /// # let space: [usize; 100] = [0; 100];
 /// # let begin: usize = space.as_ptr() as usize;
-/// # let end: usize = begin + 100 * size_of::<usize>();
 /// # let size: usize = 100 * size_of::<usize>();
+/// # let more_space: [usize; 100] = [0; 100];
+/// # let more_begin: usize = more_space.as_ptr() as usize;
+/// # let more_end: usize = more_begin + 100 * size_of::<usize>();
 /// unsafe {
 ///     heap.init(begin, size);
-///     // or
-///     heap.add_to_heap(begin, end);
+///     // more memory can be added later, as a range [start, end)
+///     heap.add_to_heap(more_begin, more_end);
 /// }
 /// ```
-pub struct Heap<const ORDER: usize> {
+pub struct Heap<const ORDER: usize, const TRACE: usize = 256> {
     // buddy system with max order of `ORDER - 1`
     free_list: [linked_list::LinkedList; ORDER],
+    // smallest order that is ever allocated
+    min_order: usize,
+    // regions of memory added to the heap
+    regions: region::Regions,
+    // regions which serve allocations above the max order
+    large: large::LargeRegions,
+    // split the largest free block instead of the smallest one that fits
+    prefer_largest: bool,
+    // number of the highest orders that smaller allocations may not split
+    reserved_orders: usize,
 
     // statistics
-    user: usize,
-    allocated: usize,
-    total: usize,
+    counters: stats::Counters<ORDER>,
+    splits: usize,
+    merges: usize,
+    outstanding: usize,
+
+    // outstanding allocations
+    #[cfg(feature = "trace")]
+    trace: trace::Trace<TRACE>,
+
+    // free blocks, to find out whether a buddy is free without searching a free list
+    #[cfg(feature = "bitmap")]
+    bitmap: Option<bitmap::Bitmap<ORDER>>,
 }
 
-impl<const ORDER: usize> Heap<ORDER> {
+/// A snapshot of the statistics of a heap
+#[cfg(feature = "stats")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// The number of bytes that user requests
+    pub user: usize,
+    /// The number of bytes that are actually allocated
+    pub allocated: usize,
+    /// The total number of bytes in the heap
+    pub total: usize,
+}
+
+/// The error returned when a heap can not satisfy an allocation
+///
+/// It tells a request that may succeed once memory is freed from one that never can. It is named
+/// after, and converts into, the unstable `core::alloc::AllocError`, but does not depend on it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocError {
+    /// No free block is currently large enough for the request
+    OutOfMemory {
+        /// The number of bytes requested
+        requested: usize,
+        /// The size of the largest free block, which may not be aligned enough for the request
+        largest_free: usize,
+    },
+    /// The request is larger than any block of the heap, so it can never be satisfied
+    SizeTooLarge {
+        /// The number of bytes requested, or the alignment if a block of that size is needed for it
+        requested: usize,
+        /// The size of the largest block the heap can hold
+        max: usize,
+    },
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AllocError::OutOfMemory {
+                requested,
+                largest_free,
+            } => write!(
+                fmt,
+                "memory allocation of {} bytes failed, the largest free block has {} bytes",
+                requested, largest_free
+            ),
+            AllocError::SizeTooLarge { requested, max } => write!(
+                fmt,
+                "memory allocation of {} bytes can never succeed, the largest block has {} bytes",
+                requested, max
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "allocator_api")]
+impl From<AllocError> for core::alloc::AllocError {
+    fn from(_: AllocError) -> Self {
+        core::alloc::AllocError
+    }
+}
+
+impl<const ORDER: usize, const TRACE: usize> Heap<ORDER, TRACE> {
     /// Create an empty heap
     pub const fn new() -> Self {
+        Self::with_min_order(0)
+    }
+
+    /// Create an empty heap that never hands out blocks smaller than 2<sup>min_order</sup> bytes
+    ///
+    /// Smaller allocations are rounded up, which wastes some memory but keeps the free lists short.
+    pub const fn with_min_order(min_order: usize) -> Self {
+        const { assert!(ORDER > 0 && ORDER <= usize::BITS as usize) };
+        assert!(min_order < ORDER);
         Heap {
             free_list: [linked_list::LinkedList::new(); ORDER],
-            user: 0,
-            allocated: 0,
-            total: 0,
+            min_order,
+            regions: region::Regions::new(),
+            prefer_largest: false,
+            reserved_orders: 0,
+            large: large::LargeRegions::new(),
+            counters: stats::Counters::new(),
+            splits: 0,
+            merges: 0,
+            outstanding: 0,
+            #[cfg(feature = "trace")]
+            trace: trace::Trace::new(),
+            #[cfg(feature = "bitmap")]
+            bitmap: None,
         }
     }
 
@@ -80,19 +243,110 @@ impl<const ORDER: usize> Heap<ORDER> {
     }
 
     /// Add a range of memory [start, end) to the heap
-    pub unsafe fn add_to_heap(&mut self, mut start: usize, mut end: usize) {
-        // avoid unaligned access on some platforms
-        start = (start + size_of::<usize>() - 1) & (!size_of::<usize>() + 1);
-        end &= !size_of::<usize>() + 1;
+    ///
+    /// The range may extend up to the very end of the address space, i.e. `end == usize::MAX`.
+    ///
+    /// Only the whole, aligned units of the heap within the range are added, so a range which is
+    /// smaller than a `usize`, or than the minimum block size, adds nothing and is silently
+    /// ignored. Use [`Heap::add_to_heap_usable`] to find out how much memory was added.
+    ///
+    /// With the `paranoid` feature enabled, this panics if the range overlaps memory that was
+    /// already added to the heap. Only 16 disjoint regions are tracked though: once more have been
+    /// added, the closest ones are treated as one, and from then on only an overlap with a free
+    /// block of the heap is detected.
+    ///
+    /// # Safety
+    ///
+    /// The range must be valid, writable memory that is not used by anything else for as long as
+    /// the heap is in use.
+    pub unsafe fn add_to_heap(&mut self, start: usize, end: usize) {
+        self.add_to_heap_usable(start, end);
+    }
+
+    /// Add a range of memory [start, end) to the heap, returning the number of bytes that were
+    /// actually added to the free lists
+    ///
+    /// This may be less than `end - start` if the range is not aligned to `usize`, and is 0 if the
+    /// range does not contain a single whole unit of the heap.
+    ///
+    /// # Safety
+    ///
+    /// See [`Heap::add_to_heap`].
+    pub unsafe fn add_to_heap_usable(&mut self, start: usize, end: usize) -> usize {
+        self.add_blocks(start, end, false)
+    }
+
+    /// Add a range of memory [start, end) to the heap like [`Heap::add_to_heap_usable`], but return
+    /// an error instead of panicking if the range is malformed
+    ///
+    /// This fails if `start > end`, or with the `paranoid` feature enabled, if the range overlaps
+    /// memory that was already added to the heap, as far as it is detected by
+    /// [`Heap::add_to_heap`]. A range in which no block of the min order fits adds
+    /// nothing. Blocks larger than the max order are split, so there is no bound on the size of
+    /// the range.
+    ///
+    /// # Safety
+    ///
+    /// See [`Heap::add_to_heap`].
+    pub unsafe fn try_add_to_heap(
+        &mut self,
+        start: usize,
+        end: usize,
+    ) -> Result<usize, &'static str> {
+        if start > end {
+            return Err("region starts after its end");
+        }
+        let (start, end) = match self.round_region(start, end) {
+            Some((start, end)) if start <= end => (start, end),
+            _ => return Ok(0),
+        };
+        #[cfg(feature = "paranoid")]
+        if self.overlaps_heap(start, end) {
+            return Err("region overlaps memory already added to the heap");
+        }
+        Ok(self.add_blocks(start, end, false))
+    }
+
+    /// Add a range of memory [start, end) to the heap, merging it with the adjacent free blocks
+    ///
+    /// This is meant for growing the heap with memory right after (or before) a region that was
+    /// already added: the free blocks on both sides of the boundary are coalesced where they are
+    /// buddies, so larger blocks become available than with [`Heap::add_to_heap`]. It is slower,
+    /// as the free lists are searched for a buddy of every new block.
+    ///
+    /// # Safety
+    ///
+    /// See [`Heap::add_to_heap`].
+    pub unsafe fn extend(&mut self, start: usize, end: usize) {
+        self.add_blocks(start, end, true);
+    }
+
+    /// Add the blocks of the range [start, end) to the free lists, merging them with their free
+    /// buddies if `merge` is `true`, and return the number of bytes added
+    unsafe fn add_blocks(&mut self, start: usize, end: usize, merge: bool) -> usize {
+        let unit = max(size_of::<usize>(), 1 << self.min_order);
         assert!(start <= end);
+        // a range within a single unit rounds to an empty one, with its start after its end
+        let (start, end) = match self.round_region(start, end) {
+            Some((start, end)) if start < end => (start, end),
+            _ => return 0,
+        };
+
+        #[cfg(feature = "paranoid")]
+        if self.overlaps_heap(start, end) {
+            panic!(
+                "region {:#x}..{:#x} overlaps memory already added to the heap",
+                start, end
+            );
+        }
 
         let mut total = 0;
         let mut current_start = start;
 
-        while current_start + size_of::<usize>() <= end {
+        while end - current_start >= unit {
             let lowbit = current_start & (!current_start + 1);
             let mut size = min(lowbit, prev_power_of_two(end - current_start));
-            
+
             // If the order of size is larger than the max order,
             // split it into smaller blocks.
             let mut order = size.trailing_zeros() as usize;
@@ -102,130 +356,1135 @@ impl<const ORDER: usize> Heap<ORDER> {
             }
             total += size;
 
-            self.free_list[order].push(current_start as *mut usize);
+            if merge {
+                self.free_block(current_start, order);
+            } else {
+                self.push_free(order, current_start);
+            }
             current_start += size;
         }
 
-        self.total += total;
+        self.regions.insert(start, current_start);
+        self.counters.add_total(total);
+        total
+    }
+
+    /// Round the range [start, end) inwards to whole units of the smallest block, or return `None`
+    /// if no unit starts before the end of the address space
+    fn round_region(&self, start: usize, end: usize) -> Option<(usize, usize)> {
+        // avoid unaligned access on some platforms, and blocks smaller than the minimum order
+        let unit = max(size_of::<usize>(), 1 << self.min_order);
+        let start = start.checked_add(unit - 1)? & (!unit + 1);
+        Some((start, end & (!unit + 1)))
     }
 
     /// Add a range of memory [start, start+size) to the heap
+    ///
+    /// # Safety
+    ///
+    /// See [`Heap::add_to_heap`].
     pub unsafe fn init(&mut self, start: usize, size: usize) {
-        self.add_to_heap(start, start + size);
+        let end = start
+            .checked_add(size)
+            .expect("heap region overflows the address space");
+        self.add_to_heap(start, end);
     }
 
-    /// Alloc a range of memory from the heap satifying `layout` requirements
-    pub fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, ()> {
-        let size = max(
-            layout.size().next_power_of_two(),
-            max(layout.align(), size_of::<usize>()),
+    /// Choose whether allocations split the largest free block rather than the smallest one that is
+    /// large enough
+    ///
+    /// Splitting from the largest block keeps the small free blocks for future small allocations.
+    pub fn set_prefer_largest(&mut self, prefer_largest: bool) {
+        self.prefer_largest = prefer_largest;
+    }
+
+    /// Keep the `orders` highest orders for allocations of those orders
+    ///
+    /// Smaller allocations fail rather than split a block of one of the `orders` highest orders, so
+    /// that a storm of small allocations can not break up the blocks needed by a few large ones.
+    /// `0`, the default, reserves nothing.
+    pub fn set_reserve_high_orders(&mut self, orders: usize) {
+        self.reserved_orders = orders;
+    }
+
+    /// Return the number of words of bitmap that [`Heap::set_bitmap`] needs to track the memory
+    /// range [start, end)
+    #[cfg(feature = "bitmap")]
+    pub fn bitmap_words(start: usize, end: usize) -> usize {
+        bitmap::Bitmap::<ORDER>::words(start, end)
+    }
+
+    /// Track which blocks of the range [start, end) are free in `bitmap`
+    ///
+    /// Deallocating a block then checks the bitmap to find out whether its buddy is free, without
+    /// searching a free list, which takes time proportional to the length of the list. The free
+    /// lists also become doubly linked, the second word of a free block pointing back to the
+    /// previous one, so that a free buddy is taken out of its list in constant time to be merged.
+    /// The bitmap needs [`Heap::bitmap_words`] words, that is about two bits for every word of the
+    /// range. Blocks outside the range are still found by searching their free list.
+    ///
+    /// The range should cover all the memory added to the heap, before or after the bitmap is set.
+    /// Setting another bitmap replaces the previous one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bitmap` is too small for the range, or if the smallest blocks can not hold two
+    /// words, i.e. the heap was not created by [`Heap::with_min_order`] with an order of at least
+    /// log<sub>2</sub>(2 * `size_of::<usize>()`).
+    #[cfg(feature = "bitmap")]
+    pub fn set_bitmap(&mut self, bitmap: &'static mut [usize], start: usize, end: usize) {
+        assert!(
+            1 << self.min_order >= 2 * size_of::<usize>(),
+            "blocks of order {} are too small for the links of a bitmap heap",
+            self.min_order
         );
+        let mut bitmap = bitmap::Bitmap::new(bitmap, start, end);
+        for (order, list) in self.free_list.iter_mut().enumerate() {
+            for block in list.iter() {
+                bitmap.set(block as usize, order, true);
+            }
+            // the blocks are at least of the min order, so they hold the link back
+            unsafe { list.link_back() };
+        }
+        self.bitmap = Some(bitmap);
+    }
+
+    /// Forget all memory added to the heap, leaving it as if newly created
+    ///
+    /// The min order, the settings of [`Heap::set_prefer_largest`] and
+    /// [`Heap::set_reserve_high_orders`], and a bitmap set with `Heap::set_bitmap` are kept.
+    ///
+    /// # Safety
+    ///
+    /// Any outstanding allocations from the heap become dangling and must not be deallocated.
+    pub unsafe fn reset(&mut self) {
+        #[cfg(feature = "bitmap")]
+        let bitmap = self.bitmap.take();
+        let (prefer_largest, reserved_orders) = (self.prefer_largest, self.reserved_orders);
+        *self = Self::with_min_order(self.min_order);
+        self.prefer_largest = prefer_largest;
+        self.reserved_orders = reserved_orders;
+        #[cfg(feature = "bitmap")]
+        {
+            self.bitmap = bitmap.map(|mut bitmap| {
+                bitmap.clear();
+                bitmap
+            });
+        }
+    }
+
+    /// Alloc a range of memory from the heap satifying `layout` requirements
+    ///
+    /// Returns `Err` rather than panicking if the request cannot be satisfied, including when it is
+    /// larger than the biggest block of 2<sup>ORDER - 1</sup> bytes.
+    ///
+    /// The allocation is taken from the smallest free block that is large enough, unless
+    /// [`Heap::set_prefer_largest`] was enabled, in which case the largest free block is split.
+    /// Within a size class, the most recently freed block is reused first, and a split block hands
+    /// out its lower half. The returned addresses are therefore reproducible: the same sequence of
+    /// operations on the same regions always returns the same addresses.
+    ///
+    /// The returned pointer is always aligned to `layout.align()`: every block is aligned to its
+    /// size, and the block is at least as large as the alignment.
+    ///
+    /// Only the size of `layout` decides how much memory is used. A request with a large alignment
+    /// needs a free block at least as large as the alignment, but only the lower part of it that
+    /// fits the size is allocated, and the rest stays free. For example,
+    /// `Layout::from_size_align(8, 4096)` uses 8 bytes rather than a whole page, at the cost of
+    /// splitting a page-sized block down to 8 bytes.
+    ///
+    /// A zero-sized `layout` does not use any memory from the heap; a dangling pointer aligned to
+    /// `layout.align()` is returned instead.
+    ///
+    /// A `layout` larger than the biggest block is served by a whole region added with
+    /// [`Heap::add_large_region`], if one is free.
+    pub fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            // The alignment of a layout is never zero
+            return Ok(unsafe { NonNull::new_unchecked(layout.align() as *mut u8) });
+        }
+
+        let size = self.block_size(&layout);
         let class = size.trailing_zeros() as usize;
-        for i in class..self.free_list.len() {
-            // Find the first non-empty size class
-            if !self.free_list[i].is_empty() {
-                // Split buffers
-                for j in (class + 1..i + 1).rev() {
-                    if let Some(block) = self.free_list[j].pop() {
-                        unsafe {
-                            self.free_list[j - 1]
-                                .push((block as usize + (1 << (j - 1))) as *mut usize);
-                            self.free_list[j - 1].push(block);
-                        }
-                    } else {
-                        return Err(());
-                    }
+        if class >= ORDER {
+            return self.alloc_large(layout).map(|(ptr, _)| ptr);
+        }
+        // Every block is aligned to its size, and splitting a block hands out its lower half which
+        // keeps that alignment, so start from the class of the alignment and split down to `class`
+        let align_class = max(class, layout.align().trailing_zeros() as usize);
+        if align_class >= ORDER {
+            return Err(AllocError::SizeTooLarge {
+                requested: layout.align(),
+                max: self.max_block_size(),
+            });
+        }
+        // Fast path: a free block of exactly the class needs no split, and is aligned to the
+        // layout if its class covers the alignment. With `prefer_largest`, a larger block may have
+        // to be split instead, which the search below decides.
+        let block = if align_class == class && !self.prefer_largest {
+            self.pop_free(class)
+        } else {
+            None
+        };
+        let block = match block {
+            Some(block) => block,
+            None => self.split_for(layout, class, align_class)?,
+        };
+        let result = NonNull::new(block as *mut u8).ok_or_else(|| self.out_of_memory(&layout))?;
+
+        // the links of the free list are the only words that are not zeroed yet
+        #[cfg(feature = "zero_on_free")]
+        unsafe {
+            core::ptr::write_bytes(block, 0, self.link_words());
+        }
+        #[cfg(feature = "trace")]
+        self.trace.record(block as usize, layout.size());
+        self.counters.add_user(layout.size());
+        self.counters.add_allocated(size);
+        self.counters.add_block(class);
+        self.outstanding += 1;
+        Ok(result)
+    }
+
+    /// Alloc exactly one block of 2<sup>order</sup> bytes, for callers such as page allocators that
+    /// deal in orders rather than layouts
+    ///
+    /// The block is aligned to its size, and is taken as by [`Heap::alloc`]. Returns `None` if no
+    /// block can be split to the order, or if the order is above the max order or below the
+    /// smallest block size of the heap.
+    pub fn alloc_order(&mut self, order: usize) -> Option<NonNull<u8>> {
+        if order >= ORDER || 1 << order < max(size_of::<usize>(), 1 << self.min_order) {
+            return None;
+        }
+        // the size and alignment are a power of two below the max order, so never overflow
+        let layout = unsafe { Layout::from_size_align_unchecked(1 << order, 1 << order) };
+        self.alloc(layout).ok()
+    }
+
+    /// Dealloc a block which was allocated by [`Heap::alloc_order`] with the same `order`
+    pub fn dealloc_order(&mut self, ptr: NonNull<u8>, order: usize) {
+        assert!(
+            order < ORDER,
+            "order {} is above the max order {}",
+            order,
+            ORDER - 1
+        );
+        self.dealloc(ptr, unsafe {
+            Layout::from_size_align_unchecked(1 << order, 1 << order)
+        });
+    }
+
+    /// Return the end of the range of classes whose blocks may be split for an allocation which
+    /// needs a block of `align_class`
+    fn end_class(&self, align_class: usize) -> usize {
+        // Blocks of the reserved orders may only be split for an allocation of a reserved order
+        let high_class = ORDER - min(self.reserved_orders, ORDER);
+        if align_class < high_class {
+            high_class
+        } else {
+            ORDER
+        }
+    }
+
+    /// Split the first suitable free block, from class `align_class` up, down to a block of
+    /// `class`, and take that block out of the free lists
+    fn split_for(
+        &mut self,
+        layout: Layout,
+        class: usize,
+        align_class: usize,
+    ) -> Result<*mut usize, AllocError> {
+        // Find the first non-empty size class, or the last one when preferring the largest
+        let mut classes =
+            (align_class..self.end_class(align_class)).filter(|&i| !self.free_list[i].is_empty());
+        let found = if self.prefer_largest {
+            classes.next_back()
+        } else {
+            classes.next()
+        };
+        let i = found.ok_or_else(|| self.out_of_memory(&layout))?;
+        // Split buffers
+        for j in (class + 1..i + 1).rev() {
+            if let Some(block) = self.pop_free(j) {
+                unsafe {
+                    self.push_free(j - 1, block as usize + (1 << (j - 1)));
+                    self.push_free(j - 1, block as usize);
                 }
+                self.splits += 1;
+            } else {
+                return Err(self.out_of_memory(&layout));
+            }
+        }
 
-                let result = NonNull::new(
-                    self.free_list[class]
-                        .pop()
-                        .expect("current block should have free space now")
-                        as *mut u8,
+        // The split above always leaves a block in `class`, so this can only fail if the free
+        // lists have been corrupted, e.g. by a double free or a use after free.
+        match self.pop_free(class) {
+            Some(block) => Ok(block),
+            None => {
+                debug_assert!(
+                    false,
+                    "free list of class {} is empty after splitting for {:?}",
+                    class, layout
                 );
-                if let Some(result) = result {
-                    self.user += layout.size();
-                    self.allocated += size;
-                    return Ok(result);
-                } else {
-                    return Err(());
-                }
+                Err(self.out_of_memory(&layout))
             }
         }
-        Err(())
+    }
+
+    /// Alloc a range of memory from the heap satifying `layout` requirements, returning it along
+    /// with the size of the block that was actually allocated
+    ///
+    /// The whole block may be used, but it must still be deallocated with `layout`. A zero-sized
+    /// `layout` gets a block of size 0, and a large region is returned whole.
+    pub fn alloc_with_size(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocError> {
+        if layout.size() == 0 {
+            return self.alloc(layout).map(|ptr| (ptr, 0));
+        }
+        let size = self.block_size(&layout);
+        if size.trailing_zeros() as usize >= ORDER {
+            return self.alloc_large(layout);
+        }
+        self.alloc(layout).map(|ptr| (ptr, size))
+    }
+
+    /// Alloc a whole large region for `layout`, returning it along with its size
+    fn alloc_large(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocError> {
+        let (start, size) = match self.large.alloc(layout.size(), layout.align()) {
+            Some(region) => region,
+            None if layout.size() > self.large.max_size() => {
+                return Err(AllocError::SizeTooLarge {
+                    requested: layout.size(),
+                    max: self.max_block_size(),
+                })
+            }
+            None => return Err(self.out_of_memory(&layout)),
+        };
+        #[cfg(feature = "trace")]
+        self.trace.record(start, layout.size());
+        self.counters.add_user(layout.size());
+        self.counters.add_allocated(size);
+        self.outstanding += 1;
+        // a large region never starts at address 0, see `add_large_region`
+        Ok((unsafe { NonNull::new_unchecked(start as *mut u8) }, size))
+    }
+
+    /// Return the error for `layout` when no free block is large enough
+    fn out_of_memory(&self, layout: &Layout) -> AllocError {
+        AllocError::OutOfMemory {
+            requested: layout.size(),
+            largest_free: max(self.max_contiguous_alloc(), self.large.max_free()),
+        }
+    }
+
+    /// Return the size of the largest block of the heap, or of its largest large region
+    fn max_block_size(&self) -> usize {
+        max(1 << (ORDER - 1), self.large.max_size())
+    }
+
+    /// Add a range of memory [start, end) which serves, as a whole, one allocation at a time that
+    /// is too large for the blocks of the heap
+    ///
+    /// Allocations of up to 2<sup>ORDER - 1</sup> bytes never use the region, and the region is
+    /// not split into blocks. The smallest free region that fits is used for a large allocation,
+    /// which must start at an address aligned to the layout. This lets a heap with a small `ORDER`
+    /// serve the occasional huge allocation from memory mapped for it. Up to 8 large regions can be
+    /// added.
+    ///
+    /// Returns an error if the range is empty or starts at address 0, or if there are too many
+    /// large regions. With the `paranoid` feature enabled, it is also an error for the range to
+    /// overlap memory already added to the heap.
+    ///
+    /// # Safety
+    ///
+    /// See [`Heap::add_to_heap`].
+    pub unsafe fn add_large_region(
+        &mut self,
+        start: usize,
+        end: usize,
+    ) -> Result<(), &'static str> {
+        if start == 0 || start >= end {
+            return Err("large region is empty or starts at address 0");
+        }
+        #[cfg(feature = "paranoid")]
+        if self.overlaps_heap(start, end) {
+            return Err("region overlaps memory already added to the heap");
+        }
+        self.large.insert(start, end)?;
+        self.regions.insert(start, end);
+        self.counters.add_total(end - start);
+        Ok(())
     }
 
     /// Dealloc a range of memory from the heap
+    ///
+    /// With the `paranoid` feature enabled, this panics if `ptr` is already in the free list.
     pub fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
-        let size = max(
-            layout.size().next_power_of_two(),
-            max(layout.align(), size_of::<usize>()),
-        );
+        if layout.size() == 0 {
+            // zero-sized allocations do not come from the heap
+            return;
+        }
+
+        let size = self.block_size(&layout);
         let class = size.trailing_zeros() as usize;
+        if class >= ORDER {
+            let size = self
+                .large
+                .dealloc(ptr.as_ptr() as usize)
+                .unwrap_or_else(|| {
+                    panic!(
+                        "{:?} is too large for a heap with max order {}",
+                        layout,
+                        ORDER - 1
+                    )
+                });
+            #[cfg(feature = "trace")]
+            self.trace.remove(ptr.as_ptr() as usize);
+            self.counters.sub_user(layout.size());
+            self.counters.sub_allocated(size);
+            self.outstanding -= 1;
+            return;
+        }
+        // a misaligned block has no buddy, and merging it would corrupt the free lists
+        debug_assert!(
+            ptr.as_ptr() as usize & (size - 1) == 0,
+            "{:#x} is not aligned to its block size {}, was it allocated with {:?}?",
+            ptr.as_ptr() as usize,
+            size,
+            layout
+        );
+
+        self.dealloc_block(ptr, class);
+        self.counters.sub_user(layout.size());
+    }
+
+    /// Grow the allocation at `ptr` from `old_layout` to `new_layout`, returning the new allocation
+    /// along with the size of its block
+    ///
+    /// The block is kept in place if `new_layout` uses a block of the same size and `ptr` is aligned
+    /// to `new_layout.align()`. Otherwise a new block is allocated, the contents are copied to it
+    /// and the old block is freed. On failure the old allocation is left untouched.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated from this heap with `old_layout`, and `new_layout.size()` must
+    /// not be smaller than `old_layout.size()`.
+    pub unsafe fn grow(
+        &mut self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<(NonNull<u8>, usize), AllocError> {
+        debug_assert!(new_layout.size() >= old_layout.size());
+        self.resize(ptr, old_layout, new_layout)
+    }
+
+    /// Like [`Heap::grow`], but the returned block is zeroed after the first `old_layout.size()`
+    /// bytes, which keep the contents of the old allocation
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Heap::grow`].
+    pub unsafe fn grow_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<(NonNull<u8>, usize), AllocError> {
+        let (new_ptr, size) = self.grow(ptr, old_layout, new_layout)?;
+        // only the tail is new: it may hold old data when the block is reused, or when it was
+        // split from a block which was used before
+        core::ptr::write_bytes(
+            new_ptr.as_ptr().add(old_layout.size()),
+            0,
+            size - old_layout.size(),
+        );
+        Ok((new_ptr, size))
+    }
+
+    /// Shrink the allocation at `ptr` from `old_layout` to `new_layout`, returning the new
+    /// allocation along with the size of its block
+    ///
+    /// The block is kept in place under the same conditions as [`Heap::grow`]. Otherwise the first
+    /// `new_layout.size()` bytes are moved to a new, smaller block.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated from this heap with `old_layout`, and `new_layout.size()` must
+    /// not be larger than `old_layout.size()`.
+    pub unsafe fn shrink(
+        &mut self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<(NonNull<u8>, usize), AllocError> {
+        debug_assert!(new_layout.size() <= old_layout.size());
+        self.resize(ptr, old_layout, new_layout)
+    }
+
+    /// Move the allocation at `ptr` from `old_layout` to `new_layout`, in place if possible
+    unsafe fn resize(
+        &mut self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<(NonNull<u8>, usize), AllocError> {
+        let size = self.block_size(&new_layout);
+        // zero-sized allocations do not own a block, and large regions may be smaller than the
+        // block size of their layout
+        if old_layout.size() != 0
+            && new_layout.size() != 0
+            && size == self.block_size(&old_layout)
+            && (size.trailing_zeros() as usize) < ORDER
+            && ptr.as_ptr() as usize & (new_layout.align() - 1) == 0
+        {
+            self.counters.sub_user(old_layout.size());
+            self.counters.add_user(new_layout.size());
+            return Ok((ptr, size));
+        }
+
+        let (new_ptr, size) = self.alloc_with_size(new_layout)?;
+        core::ptr::copy_nonoverlapping(
+            ptr.as_ptr(),
+            new_ptr.as_ptr(),
+            min(old_layout.size(), new_layout.size()),
+        );
+        self.dealloc(ptr, old_layout);
+        Ok((new_ptr, size))
+    }
+
+    /// Dealloc a block of size 2<sup>size_class</sup> bytes from the heap, for callers that do not
+    /// know the layout the block was allocated with
+    ///
+    /// The class of an allocation is the base-2 logarithm of the size returned by
+    /// [`Heap::alloc_with_size`]. As the size requested by the user is not known, the statistics of
+    /// user bytes are reduced by the whole block size.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated from this heap, must not have been deallocated yet, and
+    /// `size_class` must be exactly the class of the block that was allocated. Passing a larger or
+    /// smaller class corrupts the free lists.
+    pub unsafe fn dealloc_unchecked_size(&mut self, ptr: NonNull<u8>, size_class: usize) {
+        assert!(
+            size_class < ORDER,
+            "class {} is too large for a heap with max order {}",
+            size_class,
+            ORDER - 1
+        );
+        debug_assert!(
+            ptr.as_ptr() as usize & ((1 << size_class) - 1) == 0,
+            "{:#x} is not aligned to its block size {}",
+            ptr.as_ptr() as usize,
+            1usize << size_class
+        );
+
+        self.dealloc_block(ptr, size_class);
+        self.counters
+            .sub_user(min(self.counters.user(), 1 << size_class));
+    }
+
+    /// Dealloc the allocated block of size 2<sup>class</sup> at `ptr`, without updating the
+    /// statistics of user bytes
+    fn dealloc_block(&mut self, ptr: NonNull<u8>, class: usize) {
+        #[cfg(feature = "paranoid")]
+        if self.free_list[class]
+            .iter()
+            .any(|block| block == ptr.as_ptr() as *mut usize)
+        {
+            panic!("double free detected at {:#x}", ptr.as_ptr() as usize);
+        }
 
+        // zero the block before its first word becomes the link of a free list
+        #[cfg(feature = "zero_on_free")]
         unsafe {
-            // Put back into free list
-            self.free_list[class].push(ptr.as_ptr() as *mut usize);
-
-            // Merge free buddy lists
-            let mut current_ptr = ptr.as_ptr() as usize;
-            let mut current_class = class;
-
-            while current_class < self.free_list.len() - 1 {
-                let buddy = current_ptr ^ (1 << current_class);
-                let mut flag = false;
-                for block in self.free_list[current_class].iter_mut() {
-                    if block.value() as usize == buddy {
-                        block.pop();
-                        flag = true;
-                        break;
-                    }
-                }
+            core::ptr::write_bytes(ptr.as_ptr(), 0, 1 << class);
+        }
 
-                // Free buddy found
-                if flag {
-                    self.free_list[current_class].pop();
-                    current_ptr = min(current_ptr, buddy);
-                    current_class += 1;
-                    self.free_list[current_class].push(current_ptr as *mut usize);
-                } else {
-                    break;
+        self.free_block(ptr.as_ptr() as usize, class);
+
+        #[cfg(feature = "trace")]
+        self.trace.remove(ptr.as_ptr() as usize);
+
+        self.counters.sub_allocated(1 << class);
+        self.counters.sub_block(class);
+        self.outstanding -= 1;
+    }
+
+    /// Put the block of size 2<sup>class</sup> at `ptr` back into the free lists, merging it with
+    /// its free buddies
+    fn free_block(&mut self, ptr: usize, class: usize) {
+        // Merge free buddy lists
+        //
+        // A buddy is always the adjacent block of the same size, and it is only merged if it is
+        // itself free, so a merged block never covers memory that was not added to the heap. The
+        // buddy of a block at the edge of a region may lie outside it, but its address is only
+        // compared with the free blocks and never read, and as it only flips a bit of an aligned
+        // address, it can not wrap around.
+        let mut current_ptr = ptr;
+        let mut current_class = class;
+
+        // The top order has no larger class to merge into, so stop there
+        while current_class + 1 < self.free_list.len() {
+            let buddy = current_ptr ^ (1 << current_class);
+            if self.remove_free(current_class, buddy) {
+                // Free buddy found, its links end up inside the merged block
+                #[cfg(feature = "zero_on_free")]
+                unsafe {
+                    core::ptr::write_bytes(buddy as *mut usize, 0, self.link_words());
                 }
+                current_ptr = min(current_ptr, buddy);
+                current_class += 1;
+                self.merges += 1;
+            } else {
+                break;
+            }
+        }
+
+        // Put back into free list
+        unsafe {
+            self.push_free(current_class, current_ptr);
+        }
+    }
+
+    /// Merge every pair of free buddies left in the free lists, so that the largest possible blocks
+    /// are available
+    ///
+    /// Deallocation already merges a block with its free buddy, but blocks added with
+    /// [`Heap::add_to_heap`] are not merged with the free blocks of other regions, e.g. when adjacent
+    /// regions are added one by one after [`Heap::reset`]. Every block is compared with all the
+    /// blocks of its order, and the search starts over after each merge, so this takes time cubic
+    /// in the number of free blocks in the worst case. It is meant to be called rarely, e.g. once
+    /// after the heap has been set up.
+    pub fn merge_all(&mut self) {
+        for order in 0..ORDER - 1 {
+            while let Some(block) = self.free_list[order].iter().find(|&block| {
+                let buddy = (block as usize ^ (1 << order)) as *mut usize;
+                self.free_list[order].iter().any(|other| other == buddy)
+            }) {
+                // the block is put back and merged with its buddy, then with any larger buddies
+                self.remove_free(order, block as usize);
+                self.free_block(block as usize, order);
             }
         }
+    }
+
+    /// Push the free block at `addr` onto the free list of `order`
+    ///
+    /// # Safety
+    ///
+    /// See [`linked_list::LinkedList::push`]. With a bitmap, the block must also hold a second link.
+    unsafe fn push_free(&mut self, order: usize, addr: usize) {
+        #[cfg(feature = "bitmap")]
+        if let Some(bitmap) = &mut self.bitmap {
+            bitmap.set(addr, order, true);
+            self.free_list[order].push_linked(addr as *mut usize);
+            return;
+        }
+        self.free_list[order].push(addr as *mut usize);
+    }
+
+    /// Pop a free block from the free list of `order`
+    fn pop_free(&mut self, order: usize) -> Option<*mut usize> {
+        #[cfg(feature = "bitmap")]
+        if let Some(bitmap) = &mut self.bitmap {
+            let block = self.free_list[order].pop_linked()?;
+            bitmap.set(block as usize, order, false);
+            return Some(block);
+        }
+        self.free_list[order].pop()
+    }
+
+    /// Return the number of words at the start of a free block which hold its links
+    #[cfg(feature = "zero_on_free")]
+    fn link_words(&self) -> usize {
+        #[cfg(feature = "bitmap")]
+        if self.bitmap.is_some() {
+            return 2;
+        }
+        1
+    }
+
+    /// Remove the block at `addr` from the free list of `order`, returning `false` if it is not free
+    fn remove_free(&mut self, order: usize, addr: usize) -> bool {
+        // A block which is tracked by the bitmap does not need to be searched for: it is either not
+        // free, or unlinked through its link back
+        #[cfg(feature = "bitmap")]
+        if let Some(bitmap) = &mut self.bitmap {
+            let block = addr as *mut usize;
+            return match bitmap.get(addr, order) {
+                Some(false) => false,
+                Some(true) => {
+                    bitmap.set(addr, order, false);
+                    unsafe { self.free_list[order].unlink(block) };
+                    true
+                }
+                None => self.free_list[order].remove_linked(block),
+            };
+        }
+        self.free_list[order].remove(addr as *mut usize)
+    }
+
+    /// Return `true` if [start, end) overlaps memory added to the heap, as far as it is known
+    #[cfg(feature = "paranoid")]
+    fn overlaps_heap(&self, start: usize, end: usize) -> bool {
+        // the large regions are always known exactly
+        if self.large.overlaps(start, end) {
+            return true;
+        }
+        if self.regions.is_exact() {
+            return self.regions.overlaps(start, end);
+        }
+        // The regions could report an overlap with a gap between them once it is tracked as part
+        // of the heap, so only the free blocks are known to be in the heap
+        self.free_list.iter().enumerate().any(|(order, list)| {
+            list.iter().any(|block| {
+                let block = block as usize;
+                block < end && (start <= block || start - block < 1 << order)
+            })
+        })
+    }
+
+    /// Return the size of the block used to satisfy `layout`, which does not depend on its alignment
+    fn block_size(&self, layout: &Layout) -> usize {
+        block_size(layout, self.min_order)
+    }
+
+    /// Return the size that [`Heap::alloc_with_size`] returned for the allocation at `ptr`, which
+    /// was made with `layout` or with a smaller layout that uses the same block
+    #[cfg(feature = "allocator_api")]
+    fn allocated_size(&self, ptr: NonNull<u8>, layout: &Layout) -> usize {
+        if layout.size() == 0 {
+            return 0;
+        }
+        let size = self.block_size(layout);
+        if (size.trailing_zeros() as usize) < ORDER {
+            size
+        } else {
+            self.large
+                .used_size(ptr.as_ptr() as usize)
+                .unwrap_or(layout.size())
+        }
+    }
 
-        self.user -= layout.size();
-        self.allocated -= size;
+    /// Return the smallest order of blocks handed out by the heap
+    pub fn min_order(&self) -> usize {
+        self.min_order
     }
 
     /// Return the number of bytes that user requests
+    ///
+    /// **Without the `stats` feature, the bytes are not counted and this always returns 0.** The
+    /// other byte counts are only available with the feature.
     pub fn stats_alloc_user(&self) -> usize {
-        self.user
+        self.counters.user()
     }
 
     /// Return the number of bytes that are actually allocated
+    ///
+    /// **Without the `stats` feature, this always returns 0.**
     pub fn stats_alloc_actual(&self) -> usize {
-        self.allocated
+        self.counters.allocated()
     }
 
     /// Return the total number of bytes in the heap
+    ///
+    /// **Without the `stats` feature, this always returns 0.**
     pub fn stats_total_bytes(&self) -> usize {
-        self.total
+        self.counters.total()
+    }
+
+    /// Return the number of bytes that are still available for allocation
+    #[cfg(feature = "stats")]
+    pub fn available_bytes(&self) -> usize {
+        self.counters.total() - self.counters.allocated()
+    }
+
+    /// Return the number of times a free block has been split in two by an allocation
+    pub fn split_count(&self) -> usize {
+        self.splits
+    }
+
+    /// Return the number of times two free buddies have been merged by a deallocation
+    pub fn merge_count(&self) -> usize {
+        self.merges
+    }
+
+    /// Return the number of allocations which have not been deallocated yet
+    ///
+    /// Zero-sized allocations do not use the heap and are not counted. A heap that should be empty,
+    /// for example at shutdown, has no outstanding allocations unless some memory was leaked.
+    pub fn outstanding_allocations(&self) -> usize {
+        self.outstanding
+    }
+
+    /// Return the number of outstanding allocations of each order, i.e. of blocks of
+    /// 2<sup>order</sup> bytes, to see which sizes dominate
+    ///
+    /// Allocations from the large regions added with [`Heap::add_large_region`] are not counted.
+    #[cfg(feature = "stats")]
+    pub fn size_histogram(&self) -> [usize; ORDER] {
+        self.counters.blocks()
+    }
+
+    /// Return all the statistics of the heap at once
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> HeapStats {
+        HeapStats {
+            user: self.counters.user(),
+            allocated: self.counters.allocated(),
+            total: self.counters.total(),
+        }
+    }
+
+    /// Return the fraction of allocated bytes that were not requested by the user, from 0 to 1
+    ///
+    /// This is the memory wasted by rounding allocations up to powers of two.
+    #[cfg(feature = "stats")]
+    pub fn fragmentation(&self) -> f64 {
+        let allocated = self.counters.allocated();
+        if allocated == 0 {
+            0.0
+        } else {
+            (allocated - self.counters.user()) as f64 / allocated as f64
+        }
+    }
+
+    /// Return an iterator over all the free blocks, as `(order, addr)` pairs where the block covers
+    /// [addr, addr+2<sup>order</sup>)
+    ///
+    /// The blocks are yielded from the smallest order to the largest, and the heap is not modified.
+    pub fn iter_free(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.free_list
+            .iter()
+            .enumerate()
+            .flat_map(|(order, list)| list.iter().map(move |block| (order, block as usize)))
+    }
+
+    /// Return an iterator over the free blocks of the largest order, as `(addr, size)` pairs
+    ///
+    /// The blocks can be taken out of the heap with [`Heap::remove_from_heap`], for example to
+    /// return them to the operating system.
+    pub fn free_regions(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.free_list[ORDER - 1]
+            .iter()
+            .map(|block| (block as usize, 1 << (ORDER - 1)))
+    }
+
+    /// Take the free block [addr, addr+size) out of the heap, returning `false` if there is no such
+    /// free block
+    ///
+    /// `size` must be a power of two. Once removed, the memory is no longer used by the heap.
+    pub fn remove_from_heap(&mut self, addr: usize, size: usize) -> bool {
+        if !size.is_power_of_two() {
+            return false;
+        }
+        let class = size.trailing_zeros() as usize;
+        if class >= ORDER {
+            return false;
+        }
+
+        if self.remove_free(class, addr) {
+            self.regions.remove(addr, addr + size);
+            self.counters.sub_total(size);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Take the memory [start, end) out of the free lists, e.g. to hand it to a device for DMA,
+    /// returning `false` if part of it is not free
+    ///
+    /// The range is rounded outwards to whole units of the smallest block, and the free blocks
+    /// overlapping it are split so that the memory on either side stays in the heap. Once reserved,
+    /// the memory is no longer used by the heap and no longer counts in `Heap::total_bytes`. If
+    /// any of it is allocated or was never added to the heap, nothing is changed.
+    ///
+    /// # Safety
+    ///
+    /// The free blocks left on either side of the range get their links written, so the memory
+    /// around the range must still belong to the heap.
+    pub unsafe fn reserve(&mut self, start: usize, end: usize) -> bool {
+        assert!(start <= end);
+        let unit = max(size_of::<usize>(), 1 << self.min_order);
+        let start = start & (!unit + 1);
+        let end = match end.checked_add(unit - 1) {
+            Some(end) => end & (!unit + 1),
+            None => return false,
+        };
+
+        // check all the memory before changing anything
+        let mut current = start;
+        while current < end {
+            match self.free_block_containing(current) {
+                Some((block, order)) => current = block + (1 << order),
+                None => return false,
+            }
+        }
+
+        let mut current = start;
+        while current < end {
+            let (block, order) = self.free_block_containing(current).unwrap();
+            let block_end = block + (1 << order);
+            self.remove_free(order, block);
+            // put back the memory of the block on either side of the range, which is already split
+            // from its buddies
+            for (mut piece, piece_end) in [(block, start), (end, block_end)] {
+                while piece < piece_end {
+                    let lowbit = piece & (!piece + 1);
+                    let size = min(lowbit, prev_power_of_two(piece_end - piece));
+                    self.push_free(size.trailing_zeros() as usize, piece);
+                    piece += size;
+                }
+            }
+            current = block_end;
+        }
+
+        self.regions.remove(start, end);
+        self.counters.sub_total(end - start);
+        true
+    }
+
+    /// Return the address and the order of the free block containing `addr`, if any
+    fn free_block_containing(&self, addr: usize) -> Option<(usize, usize)> {
+        self.free_list.iter().enumerate().find_map(|(order, list)| {
+            let block = addr & !((1 << order) - 1);
+            list.iter()
+                .any(|other| other as usize == block)
+                .then_some((block, order))
+        })
+    }
+
+    /// Return an iterator over the allocations that have not been deallocated yet, as
+    /// `(ptr, size)` pairs
+    ///
+    /// Up to `TRACE` outstanding allocations are recorded. Once that many are outstanding, each new
+    /// allocation takes the place of an older one, which is then missing.
+    #[cfg(feature = "trace")]
+    pub fn leaks(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.trace.iter()
+    }
+
+    /// Check the integrity of the free lists, returning a description of the first problem found
+    ///
+    /// Every free block must be aligned to its size and lie within the regions added to the heap,
+    /// no two free blocks may overlap, and the free blocks must add up to
+    /// `Heap::available_bytes`. With a bitmap, the bitmap and the links back of the free lists
+    /// must also match the free blocks.
+    ///
+    /// This is meant for tests and fuzzing. Each block is checked against the blocks of larger
+    /// orders that would contain it. With a bitmap set by `Heap::set_bitmap`, a block is looked up
+    /// in the bitmap, so this takes time linear in the number of free blocks plus the size of the
+    /// bitmap. Without a bitmap, and for the blocks outside its range, the free lists are searched,
+    /// which takes time quadratic in the number of free blocks.
+    pub fn verify(&self) -> Result<(), &'static str> {
+        let mut free = 0usize;
+        for (order, list) in self.free_list.iter().enumerate() {
+            // A corrupted list may be cyclic, so never walk past its length. A block which is twice in
+            // the same list always makes it cyclic, as it has a single link to the next block.
+            if list.iter().take(list.len() + 1).count() != list.len() {
+                return Err("free list length does not match its number of blocks");
+            }
+            #[cfg(feature = "bitmap")]
+            let (mut tracked, mut prev) = (0, 0);
+            for block in list.iter() {
+                let addr = block as usize;
+                let size = 1usize << order;
+                if addr & (size - 1) != 0 {
+                    return Err("free block is not aligned to its size");
+                }
+                if !self.regions.contains(addr) || !self.regions.contains(addr + (size - 1)) {
+                    return Err("free block is outside the regions added to the heap");
+                }
+                #[cfg(feature = "bitmap")]
+                if let Some(bitmap) = &self.bitmap {
+                    // the block is in the heap, and the blocks of a bitmap heap hold two words
+                    if unsafe { *block.add(1) } != prev {
+                        return Err("free block does not link back to the previous one");
+                    }
+                    prev = addr;
+                    match bitmap.get(addr, order) {
+                        Some(true) => tracked += 1,
+                        Some(false) => return Err("free block is not free in the bitmap"),
+                        None => {}
+                    }
+                }
+                // aligned blocks overlap only if one contains the other
+                if (order + 1..ORDER).any(|parent_order| self.is_free(addr, parent_order)) {
+                    return Err("free blocks overlap");
+                }
+                free += size;
+            }
+            // every block which is free in the bitmap has been found in the list
+            #[cfg(feature = "bitmap")]
+            if matches!(&self.bitmap, Some(bitmap) if bitmap.count(order) != tracked) {
+                return Err("bitmap has free blocks which are not in the free list");
+            }
+        }
+        // the available bytes are not known without the statistics
+        if cfg!(feature = "stats")
+            && free + self.large.free_bytes() != self.counters.total() - self.counters.allocated()
+        {
+            return Err("free blocks do not add up to the available bytes");
+        }
+        Ok(())
+    }
+
+    /// Return `true` if the block of size 2<sup>order</sup> which contains `addr` is free
+    fn is_free(&self, addr: usize, order: usize) -> bool {
+        let block = addr & !((1 << order) - 1);
+        #[cfg(feature = "bitmap")]
+        if let Some(free) = self
+            .bitmap
+            .as_ref()
+            .and_then(|bitmap| bitmap.get(block, order))
+        {
+            return free;
+        }
+        self.free_list[order]
+            .iter()
+            .any(|other| other as usize == block)
+    }
+
+    /// Return `true` if `ptr` points into memory that was added to the heap, including the large
+    /// regions
+    ///
+    /// The heap keeps track of a limited number of disjoint regions. Past that, the closest regions
+    /// are treated as one, so this may also return `true` for a gap between two regions.
+    pub fn contains(&self, ptr: usize) -> bool {
+        self.regions.contains(ptr)
+    }
+
+    /// Return the number of free blocks of size 2<sup>order</sup>
+    pub fn free_list_len(&self, order: usize) -> usize {
+        self.free_list[order].len()
+    }
+
+    /// Return the size of the largest block that can currently be allocated, or 0 if the heap is
+    /// full
+    pub fn max_contiguous_alloc(&self) -> usize {
+        self.free_list
+            .iter()
+            .rposition(|list| !list.is_empty())
+            .map_or(0, |order| 1 << order)
+    }
+
+    /// Return `true` if [`Heap::alloc`] would currently succeed for `layout`, without changing the
+    /// heap
+    ///
+    /// This takes the alignment and [`Heap::set_reserve_high_orders`] into account, and whether a
+    /// large region added with [`Heap::add_large_region`] is free for a large `layout`.
+    pub fn can_alloc(&self, layout: Layout) -> bool {
+        if layout.size() == 0 {
+            return true;
+        }
+        let class = self.block_size(&layout).trailing_zeros() as usize;
+        if class >= ORDER {
+            return self.large.can_alloc(layout.size(), layout.align());
+        }
+        // a free block of any class from `align_class` on can be split down to `class`
+        let align_class = max(class, layout.align().trailing_zeros() as usize);
+        (align_class..self.end_class(align_class)).any(|i| !self.free_list[i].is_empty())
+    }
+
+    /// Return the number of orders of the buddy system, i.e. `ORDER`
+    pub fn order_count(&self) -> usize {
+        ORDER
+    }
+
+    /// Return the number of bytes that are actually allocated
+    #[cfg(feature = "stats")]
+    pub fn allocated_bytes(&self) -> usize {
+        self.counters.allocated()
+    }
+
+    /// Return the total number of bytes in the heap
+    #[cfg(feature = "stats")]
+    pub fn total_bytes(&self) -> usize {
+        self.counters.total()
     }
 }
 
-impl<const ORDER: usize> fmt::Debug for Heap<ORDER> {
+impl<const ORDER: usize, const TRACE: usize> Default for Heap<ORDER, TRACE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const ORDER: usize, const TRACE: usize> fmt::Debug for Heap<ORDER, TRACE> {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        fmt.debug_struct("Heap")
-            .field("user", &self.user)
-            .field("allocated", &self.allocated)
-            .field("total", &self.total)
+        let mut debug = fmt.debug_struct("Heap");
+        debug.field("min_order", &self.min_order);
+        #[cfg(feature = "stats")]
+        debug
+            .field("user", &self.counters.user())
+            .field("allocated", &self.counters.allocated())
+            .field("total", &self.counters.total());
+        debug
+            .field("splits", &self.splits)
+            .field("merges", &self.merges)
+            .field("outstanding", &self.outstanding)
             .finish()
     }
 }
 
+impl<const ORDER: usize, const TRACE: usize> fmt::Display for Heap<ORDER, TRACE> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if cfg!(not(feature = "stats")) {
+            return write!(
+                fmt,
+                "{} outstanding allocations, largest free block {}",
+                self.outstanding,
+                HumanBytes(self.max_contiguous_alloc())
+            );
+        }
+        let (user, allocated) = (self.counters.user(), self.counters.allocated());
+        // internal fragmentation in hundredths of a percent
+        let fragmentation = if allocated > 0 {
+            (allocated - user) as u128 * 10000 / allocated as u128
+        } else {
+            0
+        };
+        write!(
+            fmt,
+            "user {}, allocated {}, total {}, fragmentation {}.{:02}%",
+            HumanBytes(user),
+            HumanBytes(allocated),
+            HumanBytes(self.counters.total()),
+            fragmentation / 100,
+            fragmentation % 100
+        )
+    }
+}
+
+/// A number of bytes displayed with a binary unit
+struct HumanBytes(usize);
+
+impl fmt::Display for HumanBytes {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+        if self.0 < 1024 {
+            return write!(fmt, "{} B", self.0);
+        }
+        // widened, as shifting a 32-bit `usize` by the 40 bits of a TiB would overflow
+        let bytes = self.0 as u128;
+        let mut unit = 0;
+        while unit + 1 < UNITS.len() && bytes >> (10 * (unit + 2)) > 0 {
+            unit += 1;
+        }
+        // value in hundredths of the unit
+        let value = (bytes * 100) >> (10 * (unit + 1));
+        write!(fmt, "{}.{:02} {}", value / 100, value % 100, UNITS[unit])
+    }
+}
+
 /// A locked version of `Heap`
 ///
+/// The heap is protected by the [`lock_api::RawMutex`] `R`, which is a spinlock by default when the
+/// `use_spin` feature is enabled, or a `StdRawMutex` with the `use_std_mutex` feature. Any other
+/// lock can be plugged in with the `lock_api` feature, which has no default lock on its own.
+///
 /// # Usage
 ///
 /// Create a locked heap and add a memory region to it:
@@ -238,51 +1497,328 @@ impl<const ORDER: usize> fmt::Debug for Heap<ORDER> {
 /// let mut heap = LockedHeap::<33>::new();
 /// # let space: [usize; 100] = [0; 100];
 /// # let begin: usize = space.as_ptr() as usize;
-/// # let end: usize = begin + 100 * size_of::<usize>();
 /// # let size: usize = 100 * size_of::<usize>();
+/// # let more_space: [usize; 100] = [0; 100];
+/// # let more_begin: usize = more_space.as_ptr() as usize;
+/// # let more_end: usize = more_begin + 100 * size_of::<usize>();
 /// unsafe {
 ///     heap.lock().init(begin, size);
-///     // or
-///     heap.lock().add_to_heap(begin, end);
+///     // more memory can be added later, as a range [start, end)
+///     heap.lock().add_to_heap(more_begin, more_end);
 /// }
 /// ```
-#[cfg(feature = "use_spin")]
-pub struct LockedHeap<const ORDER: usize>(Mutex<Heap<ORDER>>);
+#[cfg(feature = "lock_api")]
+pub struct LockedHeap<const ORDER: usize, R = DefaultMutex> {
+    inner: lock_api::Mutex<R, Heap<ORDER>>,
+    oom_handler: lock_api::Mutex<R, Option<fn(&Layout)>>,
+    // copy of the min order of the heap, which never changes, to read it without locking
+    min_order: usize,
+}
 
-#[cfg(feature = "use_spin")]
-impl<const ORDER: usize> LockedHeap<ORDER> {
+#[cfg(feature = "lock_api")]
+impl<const ORDER: usize, R: RawMutex> LockedHeap<ORDER, R> {
     /// Creates an empty heap
     pub const fn new() -> Self {
-        LockedHeap(Mutex::new(Heap::<ORDER>::new()))
+        Self::with_min_order(0)
     }
 
     /// Creates an empty heap
     pub const fn empty() -> Self {
-        LockedHeap(Mutex::new(Heap::<ORDER>::new()))
+        Self::new()
+    }
+
+    /// Creates an empty heap that never hands out blocks smaller than 2<sup>min_order</sup> bytes
+    pub const fn with_min_order(min_order: usize) -> Self {
+        LockedHeap {
+            inner: lock_api::Mutex::new(Heap::<ORDER>::with_min_order(min_order)),
+            oom_handler: lock_api::Mutex::new(None),
+            min_order,
+        }
+    }
+
+    /// Return the smallest order of blocks handed out by the heap
+    pub fn min_order(&self) -> usize {
+        self.min_order
+    }
+
+    /// Creates a heap managing all of `buf`
+    ///
+    /// As the buffer is borrowed forever, this does not need the unsafe [`LockedHeap::init`].
+    ///
+    /// ```
+    /// use buddy_system_allocator::LockedHeap;
+    /// # use core::alloc::Layout;
+    /// static mut BUF: [u8; 4096] = [0; 4096];
+    /// let heap = LockedHeap::<32>::from_slice(unsafe { &mut *core::ptr::addr_of_mut!(BUF) });
+    /// assert!(heap.try_alloc(Layout::from_size_align(1024, 8).unwrap()).is_ok());
+    /// ```
+    pub fn from_slice(buf: &'static mut [u8]) -> Self {
+        let heap = Self::new();
+        let range = buf.as_mut_ptr_range();
+        unsafe {
+            heap.inner
+                .lock()
+                .add_to_heap(range.start as usize, range.end as usize)
+        };
+        heap
+    }
+
+    /// Set a function to be called when an allocation through [`GlobalAlloc`] fails, right before
+    /// the null pointer is returned
+    ///
+    /// The heap is not locked while the handler runs, so it may add memory to the heap.
+    pub fn set_oom_handler(&self, handler: fn(&Layout)) {
+        *self.oom_handler.lock() = Some(handler);
+    }
+
+    /// Call the handler set with [`LockedHeap::set_oom_handler`], if any, for a failed allocation
+    /// of `layout`
+    fn handle_oom(&self, layout: &Layout) {
+        let handler = *self.oom_handler.lock();
+        if let Some(handler) = handler {
+            handler(layout);
+        }
+    }
+
+    /// Add a range of memory [start, start+size) to the heap
+    ///
+    /// # Safety
+    ///
+    /// See [`Heap::add_to_heap`].
+    pub unsafe fn init(&self, start: usize, size: usize) {
+        self.inner.lock().init(start, size);
+    }
+
+    /// Alloc a range of memory from the heap satifying `layout` requirements
+    ///
+    /// Unlike [`GlobalAlloc::alloc`], this returns the error from [`Heap::alloc`] rather than a
+    /// null pointer, and does not call the OOM handler.
+    pub fn try_alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.inner.lock().alloc(layout)
+    }
+
+    /// Alloc a range of memory from the heap like [`LockedHeap::try_alloc`], unless the heap is
+    /// locked
+    ///
+    /// Returns `None` without waiting if the lock is held, e.g. by the code that an interrupt
+    /// handler interrupted, which would never release it while the handler spins.
+    pub fn try_lock_alloc(&self, layout: Layout) -> Option<Result<NonNull<u8>, AllocError>> {
+        self.inner.try_lock().map(|mut inner| inner.alloc(layout))
+    }
+
+    /// Lock the heap once and run `f` with it, for several operations in a row
+    ///
+    /// The lock is released when `f` returns, so unlike a guard from [`lock_api::Mutex::lock`], it
+    /// can not be held by mistake across an `await` point. `f` must not allocate from the same heap
+    /// through the [`GlobalAlloc`] implementation or any other method of the `LockedHeap`, which
+    /// would deadlock.
+    ///
+    /// This does not make the heap safe to use from an interrupt handler: an interrupt taken while
+    /// `f` runs must not lock the heap, as it would spin forever on the lock. Either keep the heap
+    /// out of interrupt handlers, or disable interrupts for the duration of the call.
+    pub fn with<T>(&self, f: impl FnOnce(&mut Heap<ORDER>) -> T) -> T {
+        f(&mut self.inner.lock())
+    }
+
+    /// Dealloc a range of memory from the heap, which was allocated by [`LockedHeap::try_alloc`]
+    pub fn dealloc_ptr(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.inner.lock().dealloc(ptr, layout)
+    }
+
+    /// Return the number of bytes that are still available for allocation
+    #[cfg(feature = "stats")]
+    pub fn available_bytes(&self) -> usize {
+        self.inner.lock().available_bytes()
+    }
+
+    /// Return a consistent snapshot of all the statistics of the heap, holding the lock only to
+    /// copy them
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> HeapStats {
+        self.inner.lock().stats()
+    }
+
+    /// Return the number of bytes that are actually allocated
+    #[cfg(feature = "stats")]
+    pub fn allocated_bytes(&self) -> usize {
+        self.inner.lock().allocated_bytes()
+    }
+
+    /// Return the total number of bytes in the heap
+    #[cfg(feature = "stats")]
+    pub fn total_bytes(&self) -> usize {
+        self.inner.lock().total_bytes()
     }
 }
 
-#[cfg(feature = "use_spin")]
-impl<const ORDER: usize> Deref for LockedHeap<ORDER> {
-    type Target = Mutex<Heap<ORDER>>;
+#[cfg(feature = "lock_api")]
+impl<const ORDER: usize, R: RawMutex> Default for LockedHeap<ORDER, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "lock_api")]
+impl<const ORDER: usize, R: RawMutex> Deref for LockedHeap<ORDER, R> {
+    type Target = lock_api::Mutex<R, Heap<ORDER>>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.inner
     }
 }
 
-#[cfg(feature = "use_spin")]
-unsafe impl<const ORDER: usize> GlobalAlloc for LockedHeap<ORDER> {
+#[cfg(feature = "lock_api")]
+unsafe impl<const ORDER: usize, R: RawMutex> GlobalAlloc for LockedHeap<ORDER, R> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        self.0
+        let result = self.inner.lock().alloc(layout);
+        match result {
+            Ok(allocation) => allocation.as_ptr(),
+            Err(_) => {
+                self.handle_oom(&layout);
+                core::ptr::null_mut()
+            }
+        }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.alloc(layout);
+        if !ptr.is_null() {
+            core::ptr::write_bytes(ptr, 0, layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner
             .lock()
-            .alloc(layout)
-            .ok()
-            .map_or(core::ptr::null_mut(), |allocation| allocation.as_ptr())
+            .dealloc(NonNull::new_unchecked(ptr), layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        // The block backing `ptr` is kept in place if it already fits the new size
+        let result = self
+            .inner
+            .lock()
+            .resize(NonNull::new_unchecked(ptr), layout, new_layout);
+        match result {
+            Ok((allocation, _)) => allocation.as_ptr(),
+            Err(_) => {
+                self.handle_oom(&new_layout);
+                core::ptr::null_mut()
+            }
+        }
+    }
+}
+
+/// A shared reference to a `LockedHeap` allocates from the heap it refers to, so that a `static`
+/// heap can be handed out by reference, e.g. as `&'static LockedHeap`.
+#[cfg(feature = "lock_api")]
+unsafe impl<const ORDER: usize, R: RawMutex> GlobalAlloc for &LockedHeap<ORDER, R> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        (**self).alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        (**self).alloc_zeroed(layout)
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout)
+        (**self).dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        (**self).realloc(ptr, layout, new_size)
+    }
+}
+
+/// A grow or shrink of a heap, i.e. [`Heap::grow`], [`Heap::grow_zeroed`] or [`Heap::shrink`]
+#[cfg(all(feature = "allocator_api", feature = "lock_api"))]
+type Resize<const ORDER: usize> = unsafe fn(
+    &mut Heap<ORDER>,
+    NonNull<u8>,
+    Layout,
+    Layout,
+) -> Result<(NonNull<u8>, usize), AllocError>;
+
+#[cfg(all(feature = "allocator_api", feature = "lock_api"))]
+impl<const ORDER: usize, R: RawMutex> LockedHeap<ORDER, R> {
+    /// Grow or shrink, with `resize`, an allocation made through [`Allocator`]
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Heap::grow`] or [`Heap::shrink`], except that `old_layout` only has to fit the
+    /// allocation.
+    unsafe fn resize_fitted(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        resize: Resize<ORDER>,
+    ) -> Result<NonNull<[u8]>, core::alloc::AllocError> {
+        let mut inner = self.inner.lock();
+        // the heap only takes `old_layout.size()` off the user bytes, see `allocate`
+        let slack = inner.allocated_size(ptr, &old_layout) - old_layout.size();
+        inner.counters.sub_user(slack);
+        match resize(&mut inner, ptr, old_layout, new_layout) {
+            Ok((allocation, size)) => {
+                inner.counters.add_user(size - new_layout.size());
+                Ok(NonNull::slice_from_raw_parts(allocation, size))
+            }
+            Err(error) => {
+                inner.counters.add_user(slack);
+                Err(error.into())
+            }
+        }
+    }
+}
+
+/// The returned slice covers the whole buddy block, so its length may exceed `layout.size()`.
+/// As the allocation may then be passed back with any layout up to that length, the whole block
+/// counts as requested by the user in the statistics.
+///
+/// `&LockedHeap` implements `Allocator` too, through the blanket impl for references.
+#[cfg(all(feature = "allocator_api", feature = "lock_api"))]
+unsafe impl<const ORDER: usize, R: RawMutex> Allocator for LockedHeap<ORDER, R> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, core::alloc::AllocError> {
+        let mut inner = self.inner.lock();
+        let (allocation, size) = inner.alloc_with_size(layout)?;
+        inner.counters.add_user(size - layout.size());
+        Ok(NonNull::slice_from_raw_parts(allocation, size))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let mut inner = self.inner.lock();
+        let slack = inner.allocated_size(ptr, &layout) - layout.size();
+        inner.counters.sub_user(slack);
+        inner.dealloc(ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, core::alloc::AllocError> {
+        self.resize_fitted(ptr, old_layout, new_layout, Heap::grow)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, core::alloc::AllocError> {
+        self.resize_fitted(ptr, old_layout, new_layout, Heap::grow_zeroed)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, core::alloc::AllocError> {
+        self.resize_fitted(ptr, old_layout, new_layout, Heap::shrink)
     }
 }
 
@@ -297,13 +1833,13 @@ unsafe impl<const ORDER: usize> GlobalAlloc for LockedHeap<ORDER> {
 /// ```
 ///
 /// Before oom, the allocator will try to call rescue function and try for one more time.
-#[cfg(feature = "use_spin")]
+#[cfg(any(feature = "use_spin", feature = "use_std_mutex"))]
 pub struct LockedHeapWithRescue<const ORDER: usize> {
     inner: Mutex<Heap<ORDER>>,
     rescue: fn(&mut Heap<ORDER>, &Layout),
 }
 
-#[cfg(feature = "use_spin")]
+#[cfg(any(feature = "use_spin", feature = "use_std_mutex"))]
 impl<const ORDER: usize> LockedHeapWithRescue<ORDER> {
     /// Creates an empty heap
     pub const fn new(rescue: fn(&mut Heap<ORDER>, &Layout)) -> Self {
@@ -314,7 +1850,7 @@ impl<const ORDER: usize> LockedHeapWithRescue<ORDER> {
     }
 }
 
-#[cfg(feature = "use_spin")]
+#[cfg(any(feature = "use_spin", feature = "use_std_mutex"))]
 impl<const ORDER: usize> Deref for LockedHeapWithRescue<ORDER> {
     type Target = Mutex<Heap<ORDER>>;
 
@@ -323,7 +1859,7 @@ impl<const ORDER: usize> Deref for LockedHeapWithRescue<ORDER> {
     }
 }
 
-#[cfg(feature = "use_spin")]
+#[cfg(any(feature = "use_spin", feature = "use_std_mutex"))]
 unsafe impl<const ORDER: usize> GlobalAlloc for LockedHeapWithRescue<ORDER> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut inner = self.inner.lock();
@@ -346,6 +1882,10 @@ unsafe impl<const ORDER: usize> GlobalAlloc for LockedHeapWithRescue<ORDER> {
     }
 }
 
-pub(crate) fn prev_power_of_two(num: usize) -> usize {
-    1 << (usize::BITS as usize - num.leading_zeros() as usize - 1)
+/// Return the size of the block used to satisfy `layout` in a heap with the given min order
+fn block_size(layout: &Layout, min_order: usize) -> usize {
+    max(
+        layout.size().next_power_of_two(),
+        max(size_of::<usize>(), 1 << min_order),
+    )
 }
@@ -5,6 +5,8 @@ use core::{fmt, ptr};
 
 /// An intrusive linked list
 ///
+/// Items are popped in the reverse order they were pushed, i.e. the list is a LIFO stack.
+///
 /// A clean room implementation of the one used in CS140e 2018 Winter
 ///
 /// Thanks Sergio Benitez for his excellent work,
@@ -12,6 +14,7 @@ use core::{fmt, ptr};
 #[derive(Copy, Clone)]
 pub struct LinkedList {
     head: *mut usize,
+    len: usize,
 }
 
 unsafe impl Send for LinkedList {}
@@ -21,6 +24,7 @@ impl LinkedList {
     pub const fn new() -> LinkedList {
         LinkedList {
             head: ptr::null_mut(),
+            len: 0,
         }
     }
 
@@ -29,10 +33,20 @@ impl LinkedList {
         self.head.is_null()
     }
 
+    /// Return the number of items in the list
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
     /// Push `item` to the front of the list
+    ///
+    /// # Safety
+    ///
+    /// `item` must be valid for writes of a `usize` and must not already be in any list.
     pub unsafe fn push(&mut self, item: *mut usize) {
         *item = self.head as usize;
         self.head = item;
+        self.len += 1;
     }
 
     /// Try to remove the first item in the list
@@ -43,13 +57,33 @@ impl LinkedList {
                 // Advance head pointer
                 let item = self.head;
                 self.head = unsafe { *item as *mut usize };
+                self.len -= 1;
                 Some(item)
             }
         }
     }
 
+    /// Return the first item in the list without removing it
+    pub fn peek(&self) -> Option<*mut usize> {
+        match self.is_empty() {
+            true => None,
+            false => Some(self.head),
+        }
+    }
+
+    /// Remove `item` from the list, returning `true` if it was found
+    pub fn remove(&mut self, item: *mut usize) -> bool {
+        match self.iter_mut().find(|node| node.value() == item) {
+            Some(node) => {
+                node.pop();
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Return an iterator over the items in the list
-    pub fn iter(&self) -> Iter {
+    pub fn iter(&self) -> Iter<'_> {
         Iter {
             curr: self.head,
             list: PhantomData,
@@ -57,15 +91,93 @@ impl LinkedList {
     }
 
     /// Return an mutable iterator over the items in the list
-    pub fn iter_mut(&mut self) -> IterMut {
+    pub fn iter_mut(&mut self) -> IterMut<'_> {
         IterMut {
             prev: &mut self.head as *mut *mut usize as *mut usize,
             curr: self.head,
+            len: &mut self.len,
             list: PhantomData,
         }
     }
 }
 
+/// Operations which also keep a link to the previous item in the second word of every item, so
+/// that an item can be removed without searching the list
+///
+/// Every item must then be valid for writes of two `usize`, and the list must only be changed with
+/// these operations.
+#[cfg(feature = "bitmap")]
+impl LinkedList {
+    /// Write the links to the previous items, e.g. of a list built with [`LinkedList::push`]
+    ///
+    /// # Safety
+    ///
+    /// Every item must be valid for writes of two `usize`.
+    pub(crate) unsafe fn link_back(&mut self) {
+        let mut prev = ptr::null_mut::<usize>();
+        for item in self.iter() {
+            *item.add(1) = prev as usize;
+            prev = item;
+        }
+    }
+
+    /// Push `item` to the front of the list, linking it back
+    ///
+    /// # Safety
+    ///
+    /// `item` must be valid for writes of two `usize` and must not already be in any list.
+    pub(crate) unsafe fn push_linked(&mut self, item: *mut usize) {
+        if !self.is_empty() {
+            *self.head.add(1) = item as usize;
+        }
+        *item.add(1) = 0;
+        self.push(item);
+    }
+
+    /// Try to remove the first item in the list, keeping the links back
+    pub(crate) fn pop_linked(&mut self) -> Option<*mut usize> {
+        let item = self.pop()?;
+        if !self.is_empty() {
+            unsafe { *self.head.add(1) = 0 };
+        }
+        Some(item)
+    }
+
+    /// Remove `item` from the list without searching it
+    ///
+    /// # Safety
+    ///
+    /// `item` must be in the list.
+    pub(crate) unsafe fn unlink(&mut self, item: *mut usize) {
+        let next = *item as *mut usize;
+        let prev = *item.add(1) as *mut usize;
+        if prev.is_null() {
+            self.head = next;
+        } else {
+            *prev = next as usize;
+        }
+        if !next.is_null() {
+            *next.add(1) = prev as usize;
+        }
+        self.len -= 1;
+    }
+
+    /// Remove `item` from the list, returning `true` if it was found
+    pub(crate) fn remove_linked(&mut self, item: *mut usize) -> bool {
+        let found = self.iter().any(|other| other == item);
+        if found {
+            unsafe { self.unlink(item) };
+        }
+        found
+    }
+}
+
+impl Default for LinkedList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl fmt::Debug for LinkedList {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_list().entries(self.iter()).finish()
@@ -97,6 +209,7 @@ impl<'a> Iterator for Iter<'a> {
 pub struct ListNode {
     prev: *mut usize,
     curr: *mut usize,
+    len: *mut usize,
 }
 
 impl ListNode {
@@ -105,6 +218,7 @@ impl ListNode {
         // Skip the current one
         unsafe {
             *(self.prev) = *(self.curr);
+            *(self.len) -= 1;
         }
         self.curr
     }
@@ -120,6 +234,7 @@ pub struct IterMut<'a> {
     list: PhantomData<&'a mut LinkedList>,
     prev: *mut usize,
     curr: *mut usize,
+    len: *mut usize,
 }
 
 impl<'a> Iterator for IterMut<'a> {
@@ -132,6 +247,7 @@ impl<'a> Iterator for IterMut<'a> {
             let res = ListNode {
                 prev: self.prev,
                 curr: self.curr,
+                len: self.len,
             };
             self.prev = self.curr;
             self.curr = unsafe { *self.curr as *mut usize };
@@ -0,0 +1,179 @@
+//! Provide a cache of small free blocks in front of a `LockedHeap`
+
+use crate::{block_size, AllocError, LockedHeap};
+use core::alloc::Layout;
+use core::ptr::NonNull;
+use lock_api::RawMutex;
+
+/// Number of size classes that are cached, i.e. blocks of up to 2<sup>MAGAZINE_CLASSES - 1</sup>
+/// bytes
+const MAGAZINE_CLASSES: usize = 12;
+
+/// A cache of small free blocks taken from a [`LockedHeap`], to be owned by a single thread or CPU
+///
+/// Small allocations and deallocations are served from up to `N` cached blocks of each size class
+/// without locking the heap. The heap is only locked to refill an empty class or to give back half
+/// of a full class, a batch of blocks at a time, which reduces lock contention when many threads
+/// allocate small objects. Allocations larger than 2 KiB, or aligned to more than their block size,
+/// go straight to the heap.
+///
+/// A magazine must always be used with the same heap. The cached blocks count as allocated in the
+/// statistics of the heap, and are leaked unless [`Magazine::flush`] is called before the magazine
+/// is dropped.
+///
+/// # Usage
+///
+/// ```
+/// use buddy_system_allocator::{LockedHeap, Magazine};
+/// # use core::alloc::Layout;
+/// static mut BUF: [u8; 4096] = [0; 4096];
+/// let heap = LockedHeap::<32>::from_slice(unsafe { &mut *core::ptr::addr_of_mut!(BUF) });
+/// let mut magazine = Magazine::<8>::new();
+///
+/// let layout = Layout::from_size_align(16, 8).unwrap();
+/// let ptr = magazine.alloc(&heap, layout).unwrap();
+/// magazine.dealloc(&heap, ptr, layout);
+/// magazine.flush(&heap);
+/// assert_eq!(heap.lock().outstanding_allocations(), 0);
+/// ```
+pub struct Magazine<const N: usize = 16> {
+    // cached blocks of each class, of which only the first `len[class]` are valid
+    blocks: [[usize; N]; MAGAZINE_CLASSES],
+    len: [usize; MAGAZINE_CLASSES],
+}
+
+impl<const N: usize> Magazine<N> {
+    /// Create an empty magazine
+    pub const fn new() -> Self {
+        const { assert!(N > 0) };
+        Magazine {
+            blocks: [[0; N]; MAGAZINE_CLASSES],
+            len: [0; MAGAZINE_CLASSES],
+        }
+    }
+
+    /// Alloc a range of memory satifying `layout` requirements, from the cache if possible,
+    /// otherwise from `heap`
+    pub fn alloc<const ORDER: usize, R: RawMutex>(
+        &mut self,
+        heap: &LockedHeap<ORDER, R>,
+        layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let class = match Self::class(heap, layout) {
+            Some(class) => class,
+            None => return heap.try_alloc(layout),
+        };
+        if self.len[class] == 0 {
+            self.refill(heap, class)?;
+        }
+        self.len[class] -= 1;
+        // cached blocks come from the heap, which never hands out a null pointer
+        Ok(unsafe { NonNull::new_unchecked(self.blocks[class][self.len[class]] as *mut u8) })
+    }
+
+    /// Dealloc a range of memory which was allocated by [`Magazine::alloc`] with the same `heap`,
+    /// keeping it in the cache if possible
+    ///
+    /// The memory may also have been allocated by another magazine of the same heap.
+    pub fn dealloc<const ORDER: usize, R: RawMutex>(
+        &mut self,
+        heap: &LockedHeap<ORDER, R>,
+        ptr: NonNull<u8>,
+        layout: Layout,
+    ) {
+        let class = match Self::class(heap, layout) {
+            Some(class) => class,
+            None => return heap.dealloc_ptr(ptr, layout),
+        };
+        if self.len[class] == N {
+            self.drain(heap, class, N.div_ceil(2));
+        }
+        self.blocks[class][self.len[class]] = ptr.as_ptr() as usize;
+        self.len[class] += 1;
+    }
+
+    /// Give all the cached blocks back to `heap`
+    pub fn flush<const ORDER: usize, R: RawMutex>(&mut self, heap: &LockedHeap<ORDER, R>) {
+        for class in 0..MAGAZINE_CLASSES {
+            self.drain(heap, class, self.len[class]);
+        }
+    }
+
+    /// Return the number of blocks in the cache
+    pub fn cached_blocks(&self) -> usize {
+        self.len.iter().sum()
+    }
+
+    /// Return the class of the blocks used for `layout`, or `None` if it is not cached
+    fn class<const ORDER: usize, R: RawMutex>(
+        heap: &LockedHeap<ORDER, R>,
+        layout: Layout,
+    ) -> Option<usize> {
+        if layout.size() == 0 {
+            return None;
+        }
+        let size = block_size(&layout, heap.min_order());
+        let class = size.trailing_zeros() as usize;
+        // the cached blocks are only aligned to their size
+        if class < MAGAZINE_CLASSES && class < ORDER && layout.align() <= size {
+            Some(class)
+        } else {
+            None
+        }
+    }
+
+    /// Fill half of the cache of `class` with blocks from `heap`, holding the lock once
+    ///
+    /// Returns the error of the heap if not even one block could be allocated.
+    fn refill<const ORDER: usize, R: RawMutex>(
+        &mut self,
+        heap: &LockedHeap<ORDER, R>,
+        class: usize,
+    ) -> Result<(), AllocError> {
+        let mut inner = heap.inner.lock();
+        while self.len[class] < N.div_ceil(2) {
+            match inner.alloc(class_layout(class)) {
+                Ok(block) => {
+                    self.blocks[class][self.len[class]] = block.as_ptr() as usize;
+                    self.len[class] += 1;
+                }
+                Err(error) if self.len[class] == 0 => return Err(error),
+                Err(_) => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Give `count` blocks of `class` back to `heap`, holding the lock once
+    fn drain<const ORDER: usize, R: RawMutex>(
+        &mut self,
+        heap: &LockedHeap<ORDER, R>,
+        class: usize,
+        count: usize,
+    ) {
+        if count == 0 {
+            return;
+        }
+        let mut inner = heap.inner.lock();
+        for _ in 0..count {
+            self.len[class] -= 1;
+            let block = self.blocks[class][self.len[class]] as *mut u8;
+            inner.dealloc(
+                unsafe { NonNull::new_unchecked(block) },
+                class_layout(class),
+            );
+        }
+    }
+}
+
+impl<const N: usize> Default for Magazine<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Return the layout of a block of `class`, which the heap allocates with a block of that class
+fn class_layout(class: usize) -> Layout {
+    // a power of two below `MAGAZINE_CLASSES` is always a valid size
+    unsafe { Layout::from_size_align_unchecked(1 << class, 1) }
+}
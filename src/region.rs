@@ -0,0 +1,129 @@
+//! Provide the bookkeeping of memory regions added to a heap
+
+/// Maximum number of disjoint regions that are tracked separately
+const MAX_REGIONS: usize = 16;
+
+/// A fixed-size set of disjoint address ranges [start, end), kept sorted by address
+///
+/// Adjacent or overlapping ranges are coalesced. If more than `MAX_REGIONS` disjoint ranges are
+/// inserted, the two ranges with the smallest gap between them are merged, so the gap is treated as
+/// part of the set from then on.
+#[derive(Clone, Copy)]
+pub(crate) struct Regions {
+    ranges: [(usize, usize); MAX_REGIONS],
+    len: usize,
+    // no gap has been merged into a range yet
+    exact: bool,
+}
+
+impl Regions {
+    /// Create an empty set of regions
+    pub(crate) const fn new() -> Self {
+        Regions {
+            ranges: [(0, 0); MAX_REGIONS],
+            len: 0,
+            exact: true,
+        }
+    }
+
+    /// Add the range [start, end) to the set
+    pub(crate) fn insert(&mut self, mut start: usize, mut end: usize) {
+        if start >= end {
+            return;
+        }
+
+        // Absorb all the ranges that overlap or touch the new one
+        let mut index = 0;
+        while index < self.len {
+            let (range_start, range_end) = self.ranges[index];
+            if range_end < start {
+                index += 1;
+            } else if range_start > end {
+                break;
+            } else {
+                start = start.min(range_start);
+                end = end.max(range_end);
+                self.remove_at(index);
+            }
+        }
+
+        if self.len == MAX_REGIONS {
+            self.merge_closest();
+            return self.insert(start, end);
+        }
+        self.ranges.copy_within(index..self.len, index + 1);
+        self.ranges[index] = (start, end);
+        self.len += 1;
+    }
+
+    /// Remove the range [start, end) from the set
+    pub(crate) fn remove(&mut self, start: usize, end: usize) {
+        let mut index = 0;
+        while index < self.len {
+            let (range_start, range_end) = self.ranges[index];
+            if range_end <= start {
+                index += 1;
+            } else if range_start >= end {
+                break;
+            } else if range_start < start && range_end > end {
+                // Split the range in two, which needs a free slot
+                if self.len == MAX_REGIONS {
+                    self.merge_closest();
+                    return self.remove(start, end);
+                }
+                self.ranges.copy_within(index + 1..self.len, index + 2);
+                self.ranges[index] = (range_start, start);
+                self.ranges[index + 1] = (end, range_end);
+                self.len += 1;
+                break;
+            } else if range_start < start {
+                self.ranges[index].1 = start;
+                index += 1;
+            } else if range_end > end {
+                self.ranges[index].0 = end;
+                break;
+            } else {
+                self.remove_at(index);
+            }
+        }
+    }
+
+    /// Return `true` if `addr` falls within one of the ranges
+    pub(crate) fn contains(&self, addr: usize) -> bool {
+        self.iter().any(|(start, end)| start <= addr && addr < end)
+    }
+
+    /// Return `true` if [start, end) shares at least one address with one of the ranges
+    #[cfg(feature = "paranoid")]
+    pub(crate) fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.iter()
+            .any(|(range_start, range_end)| range_start < end && start < range_end)
+    }
+
+    /// Return `true` if the ranges are exactly the ones inserted, i.e. no gap between two ranges
+    /// has been merged into them
+    #[cfg(feature = "paranoid")]
+    pub(crate) fn is_exact(&self) -> bool {
+        self.exact
+    }
+
+    /// Return an iterator over the ranges, sorted by address
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.ranges[..self.len].iter().copied()
+    }
+
+    fn remove_at(&mut self, index: usize) {
+        self.ranges.copy_within(index + 1..self.len, index);
+        self.len -= 1;
+    }
+
+    /// Merge the two neighbouring ranges with the smallest gap between them
+    fn merge_closest(&mut self) {
+        let index = (0..self.len - 1)
+            .min_by_key(|&i| self.ranges[i + 1].0 - self.ranges[i].1)
+            .unwrap();
+        self.ranges[index].1 = self.ranges[index + 1].1;
+        self.remove_at(index + 1);
+        self.exact = false;
+    }
+}
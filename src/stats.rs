@@ -0,0 +1,112 @@
+//! Provide the byte counters of a heap, which are left out without the `stats` feature
+
+/// The numbers of bytes requested by the user, allocated, and in total in a heap, and of
+/// outstanding blocks of each order
+///
+/// Without the `stats` feature, this is empty and nothing is counted. The counts are then only
+/// needed internally, where they are 0.
+pub(crate) struct Counters<const ORDER: usize> {
+    #[cfg(feature = "stats")]
+    user: usize,
+    #[cfg(feature = "stats")]
+    allocated: usize,
+    #[cfg(feature = "stats")]
+    total: usize,
+    #[cfg(feature = "stats")]
+    blocks: [usize; ORDER],
+}
+
+#[cfg(feature = "stats")]
+impl<const ORDER: usize> Counters<ORDER> {
+    pub(crate) const fn new() -> Self {
+        Counters {
+            user: 0,
+            allocated: 0,
+            total: 0,
+            blocks: [0; ORDER],
+        }
+    }
+
+    pub(crate) fn user(&self) -> usize {
+        self.user
+    }
+
+    pub(crate) fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    pub(crate) fn total(&self) -> usize {
+        self.total
+    }
+
+    pub(crate) fn blocks(&self) -> [usize; ORDER] {
+        self.blocks
+    }
+
+    pub(crate) fn add_user(&mut self, bytes: usize) {
+        self.user += bytes;
+    }
+
+    pub(crate) fn sub_user(&mut self, bytes: usize) {
+        self.user -= bytes;
+    }
+
+    pub(crate) fn add_allocated(&mut self, bytes: usize) {
+        self.allocated += bytes;
+    }
+
+    pub(crate) fn sub_allocated(&mut self, bytes: usize) {
+        self.allocated -= bytes;
+    }
+
+    pub(crate) fn add_total(&mut self, bytes: usize) {
+        self.total += bytes;
+    }
+
+    pub(crate) fn sub_total(&mut self, bytes: usize) {
+        self.total -= bytes;
+    }
+
+    pub(crate) fn add_block(&mut self, class: usize) {
+        self.blocks[class] += 1;
+    }
+
+    pub(crate) fn sub_block(&mut self, class: usize) {
+        self.blocks[class] -= 1;
+    }
+}
+
+#[cfg(not(feature = "stats"))]
+impl<const ORDER: usize> Counters<ORDER> {
+    pub(crate) const fn new() -> Self {
+        Counters {}
+    }
+
+    pub(crate) fn user(&self) -> usize {
+        0
+    }
+
+    pub(crate) fn allocated(&self) -> usize {
+        0
+    }
+
+    pub(crate) fn total(&self) -> usize {
+        0
+    }
+
+    pub(crate) fn add_user(&mut self, _bytes: usize) {}
+
+    pub(crate) fn sub_user(&mut self, _bytes: usize) {}
+
+    pub(crate) fn add_allocated(&mut self, _bytes: usize) {}
+
+    pub(crate) fn sub_allocated(&mut self, _bytes: usize) {}
+
+    pub(crate) fn add_total(&mut self, _bytes: usize) {}
+
+    pub(crate) fn sub_total(&mut self, _bytes: usize) {}
+
+    pub(crate) fn add_block(&mut self, _class: usize) {}
+
+    pub(crate) fn sub_block(&mut self, _class: usize) {}
+}
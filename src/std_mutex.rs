@@ -0,0 +1,55 @@
+//! Provide a lock which blocks on `std::sync` primitives instead of spinning
+
+use lock_api::{GuardSend, RawMutex};
+use std::sync::{Condvar, Mutex, PoisonError};
+
+/// A [`RawMutex`] which puts the threads waiting for it to sleep, used by [`LockedHeap`] and
+/// [`LockedFrameAllocator`] with the `use_std_mutex` feature
+///
+/// This suits hosted programs, such as userspace tests, where busy-waiting under contention would
+/// waste CPU time that the lock holder could use.
+///
+/// [`LockedHeap`]: crate::LockedHeap
+/// [`LockedFrameAllocator`]: crate::LockedFrameAllocator
+pub struct StdRawMutex {
+    // whether the lock is held, which `unlocked` is notified of when it becomes `false`
+    locked: Mutex<bool>,
+    unlocked: Condvar,
+}
+
+unsafe impl RawMutex for StdRawMutex {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = StdRawMutex {
+        locked: Mutex::new(false),
+        unlocked: Condvar::new(),
+    };
+
+    type GuardMarker = GuardSend;
+
+    fn lock(&self) {
+        // the flag is only changed while the lock of `locked` is held, so it is never left in an
+        // inconsistent state by a panic
+        let mut locked = self.locked.lock().unwrap_or_else(PoisonError::into_inner);
+        while *locked {
+            locked = self
+                .unlocked
+                .wait(locked)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        *locked = true;
+    }
+
+    fn try_lock(&self) -> bool {
+        let mut locked = self.locked.lock().unwrap_or_else(PoisonError::into_inner);
+        !core::mem::replace(&mut *locked, true)
+    }
+
+    unsafe fn unlock(&self) {
+        *self.locked.lock().unwrap_or_else(PoisonError::into_inner) = false;
+        self.unlocked.notify_one();
+    }
+
+    fn is_locked(&self) -> bool {
+        *self.locked.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -1,10 +1,14 @@
 use crate::linked_list;
+use crate::AllocError;
 use crate::FrameAllocator;
 use crate::Heap;
+use crate::LockedFrameAllocator;
+use crate::LockedHeap;
 use crate::LockedHeapWithRescue;
 use core::alloc::GlobalAlloc;
 use core::alloc::Layout;
 use core::mem::size_of;
+use core::ptr::NonNull;
 
 #[test]
 fn test_linked_list() {
@@ -93,7 +97,8 @@ fn test_heap_oom() {
 fn test_heap_oom_rescue() {
     static mut SPACE: [usize; 100] = [0; 100];
     let heap = LockedHeapWithRescue::new(|heap: &mut Heap<32>, _layout: &Layout| unsafe {
-        heap.add_to_heap(SPACE.as_ptr() as usize, SPACE.as_ptr().add(100) as usize);
+        let start = core::ptr::addr_of!(SPACE) as usize;
+        heap.add_to_heap(start, start + 100 * size_of::<usize>());
     });
 
     unsafe {
@@ -149,8 +154,8 @@ fn test_frame_allocator_add_large_size_split() {
     frame.insert(0..10_000_000_000);
 
     assert_eq!(frame.alloc(0x8000_0001), None);
+    assert_eq!(frame.alloc(0x8000_0000), Some(0));
     assert_eq!(frame.alloc(0x8000_0000), Some(0x8000_0000));
-    assert_eq!(frame.alloc(0x8000_0000), Some(0x1_0000_0000));
 }
 
 #[test]
@@ -158,7 +163,9 @@ fn test_frame_allocator_add_large_size() {
     let mut frame = FrameAllocator::<33>::new();
 
     frame.insert(0..10_000_000_000);
+    assert_eq!(frame.alloc(0x8000_0001), Some(0));
     assert_eq!(frame.alloc(0x8000_0001), Some(0x1_0000_0000));
+    assert_eq!(frame.alloc(0x8000_0001), None);
 }
 
 #[test]
@@ -238,3 +245,3667 @@ fn test_heap_merge_final_order() {
     // deallocation should not attempt to merge the two contiguous ranges as the next order does not exist
     heap.dealloc(alloc, layout);
 }
+
+#[test]
+fn test_heap_merge_into_final_order() {
+    const NUM_ORDERS: usize = 5;
+
+    let backing_size = 1 << NUM_ORDERS;
+    let backing_layout = Layout::from_size_align(backing_size, backing_size).unwrap();
+    let mut heap = Heap::<NUM_ORDERS>::new();
+    let backing_allocation = unsafe { std::alloc::alloc(backing_layout) };
+    let start = backing_allocation as usize;
+    unsafe { heap.add_to_heap(start, start + backing_size) };
+
+    // split one top-order block down to the smallest class
+    let small = Layout::from_size_align(size_of::<usize>(), 1).unwrap();
+    let blocks: std::vec::Vec<_> = (0..(1 << (NUM_ORDERS - 1)) / size_of::<usize>())
+        .map(|_| heap.alloc(small).unwrap())
+        .collect();
+
+    assert_eq!(heap.free_list_len(NUM_ORDERS - 1), 1);
+
+    // freeing them coalesces all the way up to the top order, where the buddy of the merged block
+    // is free as well but there is no larger class to merge them into
+    for block in blocks {
+        heap.dealloc(block, small);
+    }
+    assert_eq!(heap.free_list_len(NUM_ORDERS - 1), 2);
+    for order in 0..NUM_ORDERS - 1 {
+        assert_eq!(heap.free_list_len(order), 0);
+    }
+
+    // freeing a top-order block next to its free buddy leaves both at the top order too
+    let top = Layout::from_size_align(1 << (NUM_ORDERS - 1), 1).unwrap();
+    let first = heap.alloc(top).unwrap();
+    let second = heap.alloc(top).unwrap();
+    assert!(heap.alloc(small).is_err());
+    heap.dealloc(first, top);
+    heap.dealloc(second, top);
+    assert_eq!(heap.free_list_len(NUM_ORDERS - 1), 2);
+    assert_eq!(heap.verify(), Ok(()));
+
+    unsafe { std::alloc::dealloc(backing_allocation, backing_layout) };
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_heap_available_bytes() {
+    let mut heap = Heap::<32>::new();
+    assert_eq!(heap.total_bytes(), 0);
+    assert_eq!(heap.available_bytes(), 0);
+
+    let space: [usize; 100] = [0; 100];
+    unsafe {
+        heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize);
+    }
+    let total = heap.total_bytes();
+    assert_eq!(total, 100 * size_of::<usize>());
+    assert_eq!(heap.available_bytes(), total);
+    assert_eq!(heap.allocated_bytes(), 0);
+
+    let layout = Layout::from_size_align(3 * size_of::<usize>(), 1).unwrap();
+    let addr = heap.alloc(layout).unwrap();
+    assert_eq!(heap.allocated_bytes(), 4 * size_of::<usize>());
+    assert_eq!(heap.available_bytes(), total - 4 * size_of::<usize>());
+
+    heap.dealloc(addr, layout);
+    assert_eq!(heap.allocated_bytes(), 0);
+    assert_eq!(heap.available_bytes(), total);
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_locked_heap_available_bytes() {
+    let heap = LockedHeap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    unsafe {
+        heap.lock()
+            .add_to_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize);
+    }
+    let layout = Layout::from_size_align(size_of::<usize>(), 1).unwrap();
+    let addr = unsafe { heap.alloc(layout) };
+    assert_eq!(heap.total_bytes(), 100 * size_of::<usize>());
+    assert_eq!(heap.allocated_bytes(), size_of::<usize>());
+    assert_eq!(heap.available_bytes(), 99 * size_of::<usize>());
+    unsafe { heap.dealloc(addr, layout) };
+    assert_eq!(heap.available_bytes(), heap.total_bytes());
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_heap_orders() {
+    fn check<const ORDER: usize>() {
+        let mut heap = Heap::<ORDER>::new();
+        let space: [usize; 100] = [0; 100];
+        unsafe {
+            heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize);
+        }
+        let layout = Layout::from_size_align(2 * size_of::<usize>(), 1).unwrap();
+        let addr = heap.alloc(layout).unwrap();
+        heap.dealloc(addr, layout);
+        assert_eq!(heap.allocated_bytes(), 0);
+    }
+
+    check::<16>();
+    check::<48>();
+
+    // `new` stays usable in a const context for any order
+    const SMALL: Heap<16> = Heap::<16>::new();
+    const LARGE: Heap<48> = Heap::<48>::new();
+    assert_eq!(SMALL.total_bytes(), 0);
+    assert_eq!(LARGE.total_bytes(), 0);
+}
+
+#[test]
+fn test_heap_truncated_returns_err() {
+    let mut heap = Heap::<32>::new();
+    let space: [usize; 4] = [0; 4];
+    unsafe {
+        // only hand half of the space to the heap
+        heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(2) as usize);
+    }
+
+    let layout = Layout::from_size_align(size_of::<usize>(), 1).unwrap();
+    let first = heap.alloc(layout).unwrap();
+    let second = heap.alloc(layout).unwrap();
+    assert!(heap.alloc(layout).is_err());
+    assert!(heap
+        .alloc(Layout::from_size_align(4 * size_of::<usize>(), 1).unwrap())
+        .is_err());
+    heap.dealloc(first, layout);
+    heap.dealloc(second, layout);
+}
+
+#[test]
+fn test_heap_init_matches_add_to_heap() {
+    let space: [usize; 100] = [0; 100];
+    let begin = space.as_ptr() as usize;
+    let size = 100 * size_of::<usize>();
+
+    let mut added = Heap::<32>::new();
+    unsafe { added.add_to_heap(begin, begin + size) };
+    let added_lists = format!("{:?}", added.free_list);
+
+    let mut inited = Heap::<32>::new();
+    unsafe { inited.init(begin, size) };
+    assert_eq!(format!("{:?}", inited.free_list), added_lists);
+    #[cfg(feature = "stats")]
+    assert_eq!(inited.total_bytes(), added.total_bytes());
+
+    let locked = LockedHeap::<32>::new();
+    unsafe { locked.init(begin, size) };
+    assert_eq!(format!("{:?}", locked.lock().free_list), added_lists);
+}
+
+#[test]
+#[should_panic(expected = "overflows the address space")]
+fn test_heap_init_overflow() {
+    let mut heap = Heap::<32>::new();
+    unsafe { heap.init(usize::MAX - 8, 16) };
+}
+
+#[test]
+fn test_locked_heap_alloc_zeroed() {
+    let heap = LockedHeap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    unsafe { heap.init(space.as_ptr() as usize, 100 * size_of::<usize>()) };
+
+    let layout = Layout::from_size_align(4 * size_of::<usize>(), 1).unwrap();
+    unsafe {
+        let dirty = heap.alloc(layout);
+        core::ptr::write_bytes(dirty, 0xa5, layout.size());
+        heap.dealloc(dirty, layout);
+
+        let zeroed = heap.alloc_zeroed(layout);
+        // the freed block is handed out again
+        assert_eq!(zeroed, dirty);
+        let bytes = core::slice::from_raw_parts(zeroed, layout.size());
+        assert!(bytes.iter().all(|&b| b == 0));
+        heap.dealloc(zeroed, layout);
+    }
+}
+
+#[test]
+fn test_heap_free_list_len() {
+    let mut heap = Heap::<32>::new();
+    assert_eq!(heap.order_count(), 32);
+
+    // 7 words starting at a 8-word boundary split into blocks of 4, 2 and 1 words
+    let layout = Layout::new::<[usize; 8]>()
+        .align_to(size_of::<[usize; 8]>())
+        .unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    let begin = space as usize;
+    unsafe { heap.add_to_heap(begin, begin + 7 * size_of::<usize>()) };
+
+    let word_order = size_of::<usize>().trailing_zeros() as usize;
+    for order in 0..heap.order_count() {
+        let expected = if (word_order..word_order + 3).contains(&order) {
+            1
+        } else {
+            0
+        };
+        assert_eq!(heap.free_list_len(order), expected, "order {}", order);
+    }
+
+    unsafe { std::alloc::dealloc(space, layout) };
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_locked_heap_realloc() {
+    let heap = LockedHeap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    unsafe { heap.init(space.as_ptr() as usize, 100 * size_of::<usize>()) };
+
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(3 * word, 1).unwrap();
+    unsafe {
+        let ptr = heap.alloc(layout);
+        for i in 0..layout.size() {
+            *ptr.add(i) = i as u8;
+        }
+
+        // growing within the same 4-word block stays in place
+        let same = heap.realloc(ptr, layout, 4 * word);
+        assert_eq!(same, ptr);
+        assert_eq!(heap.lock().stats_alloc_user(), 4 * word);
+        let layout = Layout::from_size_align(4 * word, 1).unwrap();
+
+        // growing into the next class moves the data
+        let grown = heap.realloc(same, layout, 5 * word);
+        assert_ne!(grown, same);
+        for i in 0..3 * word {
+            assert_eq!(*grown.add(i), i as u8);
+        }
+        assert_eq!(heap.lock().stats_alloc_user(), 5 * word);
+        assert_eq!(heap.allocated_bytes(), 8 * word);
+        let layout = Layout::from_size_align(5 * word, 1).unwrap();
+
+        // shrinking keeps the prefix
+        let shrunk = heap.realloc(grown, layout, word);
+        for i in 0..word {
+            assert_eq!(*shrunk.add(i), i as u8);
+        }
+        assert_eq!(heap.lock().stats_alloc_user(), word);
+        assert_eq!(heap.allocated_bytes(), word);
+        let layout = Layout::from_size_align(word, 1).unwrap();
+
+        // growing beyond the heap fails and leaves the allocation untouched
+        assert!(heap.realloc(shrunk, layout, 200 * word).is_null());
+        assert_eq!(heap.lock().stats_alloc_user(), word);
+        for i in 0..word {
+            assert_eq!(*shrunk.add(i), i as u8);
+        }
+        heap.dealloc(shrunk, layout);
+    }
+    assert_eq!(heap.allocated_bytes(), 0);
+}
+
+#[test]
+#[cfg(feature = "paranoid")]
+#[should_panic(expected = "double free detected")]
+fn test_heap_double_free() {
+    let mut heap = Heap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    unsafe { heap.init(space.as_ptr() as usize, 100 * size_of::<usize>()) };
+
+    let layout = Layout::from_size_align(size_of::<usize>(), 1).unwrap();
+    let first = heap.alloc(layout).unwrap();
+    // keep the buddy allocated so that the freed block is not merged away
+    let _second = heap.alloc(layout).unwrap();
+    heap.dealloc(first, layout);
+    heap.dealloc(first, layout);
+}
+
+#[test]
+fn test_frame_allocator_small_order() {
+    // Max size of block is 2^19 frames
+    let mut frame = FrameAllocator::<20>::new();
+    frame.add_frame(1 << 20, 2 << 20);
+
+    assert_eq!(frame.alloc((1 << 19) + 1), None);
+    let large = frame.alloc(1 << 19).unwrap();
+    let medium = frame.alloc(1 << 10).unwrap();
+    let small = frame.alloc(1).unwrap();
+    assert_eq!(large % (1 << 19), 0);
+    assert_eq!(medium % (1 << 10), 0);
+    assert!(small < medium || small >= medium + (1 << 10));
+
+    frame.dealloc(small, 1);
+    frame.dealloc(medium, 1 << 10);
+    frame.dealloc(large, 1 << 19);
+    assert_eq!(frame.alloc(1 << 19), Some(1 << 20));
+    assert_eq!(frame.alloc(1 << 19), Some(3 << 19));
+}
+
+#[test]
+fn test_frame_allocator_default_order() {
+    let mut frame: FrameAllocator = FrameAllocator::new();
+    frame.add_frame(1 << 32, 1 << 33);
+    assert_eq!(frame.alloc(1 << 32), Some(1 << 32));
+
+    let locked: LockedFrameAllocator = LockedFrameAllocator::new();
+    locked.lock().add_frame(0, 4);
+    assert_eq!(locked.lock().alloc(4), Some(0));
+
+    // the names without an order, as used before it was a parameter
+    struct Frames {
+        free: FrameAllocator,
+    }
+    static LOCKED: LockedFrameAllocator = LockedFrameAllocator::new();
+    let mut frames = Frames {
+        free: FrameAllocator::default(),
+    };
+    frames.free.add_frame(0, 1 << 33);
+    assert_eq!(frames.free.alloc(1 << 32), Some(0));
+    assert_eq!(frames.free.alloc(1 << 32), Some(1 << 32));
+    LOCKED.add_frame(0, 1);
+    assert_eq!(LOCKED.alloc(1), Some(0));
+}
+
+#[test]
+fn test_frame_allocator_aligned_huge_page() {
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(3, 4096);
+
+    // fragment the low frames first
+    let small = frame.alloc(3).unwrap();
+    for align_log2 in [0, 3, 9, 10] {
+        let layout = Layout::from_size_align(3, 1 << align_log2).unwrap();
+        let start = frame.alloc_aligned(layout).unwrap();
+        assert_eq!(start % (1 << align_log2), 0, "align 2^{}", align_log2);
+        frame.dealloc_aligned(start, layout);
+    }
+    frame.dealloc(small, 3);
+}
+
+#[test]
+fn test_frame_allocator_stats() {
+    let mut frame = FrameAllocator::<32>::new();
+    assert_eq!(frame.total_frames(), 0);
+    assert_eq!(frame.available_frames(), 0);
+
+    frame.add_frame(0, 100);
+    assert_eq!(frame.total_frames(), 100);
+    assert_eq!(frame.allocated_frames(), 0);
+    assert_eq!(frame.available_frames(), 100);
+
+    let addr = frame.alloc(3).unwrap();
+    assert_eq!(frame.allocated_frames(), 4);
+    assert_eq!(frame.available_frames(), 96);
+
+    frame.dealloc(addr, 3);
+    assert_eq!(frame.allocated_frames(), 0);
+    assert_eq!(frame.available_frames(), 100);
+}
+
+#[test]
+fn test_heap_add_usable_bytes() {
+    let mut heap = Heap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    let begin = space.as_ptr() as usize;
+    let word = size_of::<usize>();
+
+    // an aligned region is added in full
+    let added = unsafe { heap.add_to_heap_usable(begin, begin + 10 * word) };
+    assert_eq!(added, 10 * word);
+
+    // a misaligned region loses the partial words at both ends
+    let added = unsafe { heap.add_to_heap_usable(begin + 20 * word + 1, begin + 30 * word - 1) };
+    assert_eq!(added, 8 * word);
+    #[cfg(feature = "stats")]
+    assert_eq!(heap.total_bytes(), 18 * word);
+}
+
+#[test]
+fn test_heap_add_region_smaller_than_word() {
+    let mut heap = Heap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    let begin = space.as_ptr() as usize;
+    let word = size_of::<usize>();
+
+    // half a word, i.e. 4 bytes on a 64-bit target, whether aligned or not
+    let added = unsafe { heap.add_to_heap_usable(begin, begin + word / 2) };
+    assert_eq!(added, 0);
+    let added = unsafe { heap.add_to_heap_usable(begin + word + 1, begin + word + 1 + word / 2) };
+    assert_eq!(added, 0);
+    assert_eq!(
+        unsafe { heap.try_add_to_heap(begin + 2 * word, begin + 2 * word + 1) },
+        Ok(0)
+    );
+    unsafe { heap.add_to_heap(begin + 3 * word, begin + 3 * word + word / 2) };
+    #[cfg(feature = "stats")]
+    assert_eq!(heap.total_bytes(), 0);
+    assert!(heap.alloc(Layout::from_size_align(1, 1).unwrap()).is_err());
+
+    // the heap is still usable once a whole word is added
+    let added = unsafe { heap.add_to_heap_usable(begin + 4 * word, begin + 5 * word) };
+    assert_eq!(added, word);
+    #[cfg(feature = "stats")]
+    assert_eq!(heap.total_bytes(), word);
+}
+
+#[test]
+#[cfg(feature = "allocator_api")]
+fn test_locked_heap_allocator_api() {
+    use core::alloc::Allocator;
+
+    let heap = LockedHeap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    unsafe { heap.init(space.as_ptr() as usize, 100 * size_of::<usize>()) };
+
+    // the returned slice covers the whole rounded-up block
+    let layout = Layout::from_size_align(3 * size_of::<usize>(), 1).unwrap();
+    let block = heap.allocate(layout).unwrap();
+    assert_eq!(block.len(), 4 * size_of::<usize>());
+    unsafe { heap.deallocate(block.cast(), layout) };
+
+    let mut v = std::vec::Vec::with_capacity_in(10, &heap);
+    v.extend(0..10usize);
+    assert_eq!(v.iter().sum::<usize>(), 45);
+    assert_eq!(heap.lock().outstanding_allocations(), 1);
+    drop(v);
+    assert_eq!(heap.lock().outstanding_allocations(), 0);
+
+    // growing into the slack of the block keeps it
+    let layout = Layout::from_size_align(3 * size_of::<usize>(), 1).unwrap();
+    let block = heap.allocate(layout).unwrap().cast::<u8>();
+    let new_layout = Layout::from_size_align(4 * size_of::<usize>(), 1).unwrap();
+    let grown = unsafe { heap.grow_zeroed(block, layout, new_layout) }.unwrap();
+    assert_eq!(grown.cast(), block);
+    let shrunk = unsafe { heap.shrink(grown.cast(), new_layout, layout) }.unwrap();
+    assert_eq!(shrunk.len(), 4 * size_of::<usize>());
+    unsafe { heap.deallocate(shrunk.cast(), layout) };
+    assert_eq!(heap.lock().outstanding_allocations(), 0);
+}
+
+#[test]
+#[cfg(all(feature = "allocator_api", feature = "stats"))]
+fn test_locked_heap_allocator_api_returned_length() {
+    use core::alloc::Allocator;
+
+    let heap = LockedHeap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    unsafe { heap.init(space.as_ptr() as usize, 100 * size_of::<usize>()) };
+    let word = size_of::<usize>();
+
+    // any size up to the returned length fits the allocation, and the whole block is counted
+    let layout = Layout::from_size_align(3 * word, word).unwrap();
+    let block = heap.allocate(layout).unwrap();
+    assert_eq!(block.len(), 4 * word);
+    assert_eq!(heap.lock().stats_alloc_user(), 4 * word);
+    let fitted = Layout::from_size_align(block.len(), word).unwrap();
+    unsafe { heap.deallocate(block.cast(), fitted) };
+    assert_eq!(heap.lock().stats_alloc_user(), 0);
+    assert_eq!(heap.allocated_bytes(), 0);
+
+    // shrinking with the returned length, then growing back with the new returned length
+    let block = heap.allocate(layout).unwrap();
+    let fitted = Layout::from_size_align(block.len(), word).unwrap();
+    let small = Layout::from_size_align(word, word).unwrap();
+    let shrunk = unsafe { heap.shrink(block.cast(), fitted, small) }.unwrap();
+    assert_eq!(shrunk.len(), word);
+    assert_eq!(heap.lock().stats_alloc_user(), word);
+    let fitted = Layout::from_size_align(shrunk.len(), word).unwrap();
+    let grown = unsafe { heap.grow(shrunk.cast(), fitted, layout) }.unwrap();
+    assert_eq!(heap.lock().stats_alloc_user(), 4 * word);
+    let fitted = Layout::from_size_align(grown.len(), word).unwrap();
+    unsafe { heap.deallocate(grown.cast(), fitted) };
+    assert_eq!(heap.lock().stats_alloc_user(), 0);
+    assert_eq!(heap.allocated_bytes(), 0);
+
+    // a failed grow leaves the statistics as they were
+    let block = heap.allocate(layout).unwrap();
+    let huge = Layout::from_size_align(200 * word, word).unwrap();
+    assert!(unsafe { heap.grow(block.cast(), layout, huge) }.is_err());
+    assert_eq!(heap.lock().stats_alloc_user(), 4 * word);
+    unsafe { heap.deallocate(block.cast(), layout) };
+    assert_eq!(heap.lock().stats_alloc_user(), 0);
+}
+
+#[test]
+fn test_linked_list_len() {
+    let mut values = [0usize; 4];
+    let mut list = linked_list::LinkedList::new();
+    assert_eq!(list.len(), 0);
+    assert!(list.is_empty());
+
+    unsafe {
+        for value in values.iter_mut() {
+            list.push(value as *mut usize);
+        }
+    }
+    assert_eq!(list.len(), 4);
+
+    list.pop();
+    assert_eq!(list.len(), 3);
+
+    // remove the middle item of the remaining three
+    let node = list.iter_mut().nth(1).unwrap();
+    assert_eq!(node.pop(), &mut values[1] as *mut usize);
+    assert_eq!(list.len(), 2);
+    assert_eq!(list.len(), list.iter().count());
+
+    unsafe { list.push(&mut values[3] as *mut usize) };
+    assert_eq!(list.len(), 3);
+
+    // remove the tail
+    let node = list.iter_mut().last().unwrap();
+    assert_eq!(node.pop(), &mut values[0] as *mut usize);
+    assert_eq!(list.len(), 2);
+
+    list.pop();
+    list.pop();
+    assert_eq!(list.len(), 0);
+    assert!(list.is_empty());
+    assert_eq!(list.pop(), None);
+    assert_eq!(list.len(), 0);
+
+    // the dealloc merge path removes buddies through `iter_mut`
+    let mut heap = Heap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    unsafe { heap.init(space.as_ptr() as usize, 100 * size_of::<usize>()) };
+    let layout = Layout::from_size_align(size_of::<usize>(), 1).unwrap();
+    let blocks: std::vec::Vec<_> = (0..20).map(|_| heap.alloc(layout).unwrap()).collect();
+    for block in blocks.into_iter().rev() {
+        heap.dealloc(block, layout);
+        for list in heap.free_list.iter() {
+            assert_eq!(list.len(), list.iter().count());
+        }
+    }
+}
+
+#[test]
+fn test_heap_add_at_end_of_address_space() {
+    let mut heap = Heap::<32>::new();
+    let word = size_of::<usize>();
+
+    // neither range has room for a whole aligned word, so nothing is written to them
+    unsafe {
+        assert_eq!(
+            heap.add_to_heap_usable(usize::MAX - word + 1, usize::MAX),
+            0
+        );
+        assert_eq!(heap.add_to_heap_usable(usize::MAX - 2, usize::MAX), 0);
+        heap.add_to_heap(usize::MAX, usize::MAX);
+    }
+    #[cfg(feature = "stats")]
+    assert_eq!(heap.total_bytes(), 0);
+}
+
+#[test]
+fn test_heap_max_contiguous_alloc() {
+    let mut heap = Heap::<32>::new();
+    assert_eq!(heap.max_contiguous_alloc(), 0);
+
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(16 * word, 16 * word).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    unsafe { heap.init(space as usize, 16 * word) };
+    assert_eq!(heap.max_contiguous_alloc(), 16 * word);
+
+    // fragment the heap: taking one word leaves free blocks of 1, 2, 4 and 8 words
+    let small = Layout::from_size_align(word, 1).unwrap();
+    let first = heap.alloc(small).unwrap();
+    let max = heap.max_contiguous_alloc();
+    assert_eq!(max, 8 * word);
+
+    assert!(heap
+        .alloc(Layout::from_size_align(max + 1, 1).unwrap())
+        .is_err());
+    let big = Layout::from_size_align(max, 1).unwrap();
+    let addr = heap.alloc(big).unwrap();
+    assert_eq!(heap.max_contiguous_alloc(), 4 * word);
+
+    heap.dealloc(addr, big);
+    heap.dealloc(first, small);
+    assert_eq!(heap.max_contiguous_alloc(), 16 * word);
+    unsafe { std::alloc::dealloc(space, layout) };
+}
+
+#[test]
+fn test_frame_allocator_free_ranges() {
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(100, 1024);
+    let a = frame.alloc(3).unwrap();
+    let b = frame.alloc(100).unwrap();
+    let c = frame.alloc(1).unwrap();
+    frame.dealloc(a, 3);
+
+    let mut ranges: std::vec::Vec<_> = frame.free_ranges().collect();
+    ranges.sort_by_key(|range| range.start);
+    for pair in ranges.windows(2) {
+        assert!(pair[0].end <= pair[1].start);
+    }
+    for range in ranges.iter() {
+        assert!(!range.contains(&b) && !range.contains(&c));
+    }
+    let free: usize = ranges.iter().map(|range| range.len()).sum();
+    assert_eq!(free, frame.available_frames());
+
+    frame.dealloc(b, 100);
+    frame.dealloc(c, 1);
+    let free: usize = frame.free_ranges().map(|range| range.len()).sum();
+    assert_eq!(free, 1024 - 100);
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_heap_reset() {
+    let mut heap = Heap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    let begin = space.as_ptr() as usize;
+    let size = 100 * size_of::<usize>();
+    unsafe { heap.init(begin, size) };
+
+    let layout = Layout::from_size_align(16 * size_of::<usize>(), 1).unwrap();
+    heap.alloc(layout).unwrap();
+    heap.alloc(layout).unwrap();
+
+    unsafe { heap.reset() };
+    assert_eq!(heap.total_bytes(), 0);
+    assert_eq!(heap.allocated_bytes(), 0);
+    assert_eq!(heap.stats_alloc_user(), 0);
+    assert!(heap.alloc(layout).is_err());
+
+    unsafe { heap.init(begin, size) };
+    assert_eq!(heap.available_bytes(), size);
+    let word = Layout::from_size_align(size_of::<usize>(), 1).unwrap();
+    for _ in 0..100 {
+        assert!(heap.alloc(word).is_ok());
+    }
+    assert!(heap.alloc(word).is_err());
+}
+
+#[test]
+fn test_heap_reset_keeps_settings() {
+    let word = size_of::<usize>();
+    // a small block at the start of the space and a large one at its end
+    let space = std::vec![0usize; 128 + 64].leak();
+    let begin = (space.as_ptr() as usize + 64 * word - 1) & !(64 * word - 1);
+    let small = (begin, begin + 4 * word);
+    let large = (begin + 64 * word, begin + 128 * word);
+    let layout = Layout::from_size_align(word, word).unwrap();
+
+    let mut heap = Heap::<32>::new();
+    heap.set_prefer_largest(true);
+    unsafe {
+        heap.add_to_heap(small.0, small.1);
+        heap.add_to_heap(large.0, large.1);
+    }
+    let addr = heap.alloc(layout).unwrap().as_ptr() as usize;
+    assert!(addr >= large.0 && addr < large.1);
+
+    unsafe {
+        heap.reset();
+        heap.add_to_heap(small.0, small.1);
+        heap.add_to_heap(large.0, large.1);
+    }
+    let addr = heap.alloc(layout).unwrap().as_ptr() as usize;
+    assert!(addr >= large.0 && addr < large.1);
+    assert_eq!(heap.verify(), Ok(()));
+
+    // the large block is of the top order, which is reserved
+    const ORDER: usize = (64 * size_of::<usize>()).trailing_zeros() as usize + 1;
+    let mut heap = Heap::<ORDER>::new();
+    heap.set_reserve_high_orders(1);
+    unsafe { heap.add_to_heap(large.0, large.1) };
+    assert!(!heap.can_alloc(layout));
+
+    unsafe {
+        heap.reset();
+        heap.add_to_heap(large.0, large.1);
+    }
+    assert!(!heap.can_alloc(layout));
+    assert!(heap.alloc(layout).is_err());
+    let top = Layout::from_size_align(64 * word, word).unwrap();
+    assert!(heap.can_alloc(top));
+}
+
+#[test]
+fn test_locked_heap_oom_handler() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static HEAP: LockedHeap<32> = LockedHeap::<32>::new();
+    static OOM_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+    HEAP.set_oom_handler(|layout| {
+        // the heap lock must not be held here
+        #[cfg(feature = "stats")]
+        assert_eq!(HEAP.lock().total_bytes(), 0);
+        OOM_SIZE.store(layout.size(), Ordering::SeqCst);
+    });
+
+    let layout = Layout::from_size_align(42, 2).unwrap();
+    assert!(unsafe { HEAP.alloc(layout) }.is_null());
+    assert_eq!(OOM_SIZE.load(Ordering::SeqCst), 42);
+}
+
+#[test]
+fn test_heap_no_merge_across_gap() {
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(64 * word, 64 * word).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    let begin = space as usize;
+
+    // two regions, each the buddy-sized neighbour of the unmapped gap between them
+    let mut heap = Heap::<32>::new();
+    unsafe {
+        heap.add_to_heap(begin, begin + 16 * word);
+        heap.add_to_heap(begin + 32 * word, begin + 48 * word);
+    }
+
+    let small = Layout::from_size_align(word, 1).unwrap();
+    let blocks: std::vec::Vec<_> = (0..32).map(|_| heap.alloc(small).unwrap()).collect();
+    assert!(heap.alloc(small).is_err());
+    for block in blocks {
+        heap.dealloc(block, small);
+    }
+
+    // everything coalesced back into one block per region, but never across the gap
+    assert_eq!(heap.max_contiguous_alloc(), 16 * word);
+    assert_eq!(heap.free_list_len((16 * word).trailing_zeros() as usize), 2);
+    assert!(heap
+        .alloc(Layout::from_size_align(32 * word, 1).unwrap())
+        .is_err());
+
+    unsafe { std::alloc::dealloc(space, layout) };
+}
+
+#[test]
+fn test_locked_heap_try_alloc() {
+    let heap = LockedHeap::<32>::new();
+    let layout = Layout::from_size_align(size_of::<usize>(), 1).unwrap();
+    assert!(heap.try_alloc(layout).is_err());
+
+    let space: [usize; 100] = [0; 100];
+    unsafe { heap.init(space.as_ptr() as usize, 100 * size_of::<usize>()) };
+    let addr = heap.try_alloc(layout).unwrap();
+    assert!(heap
+        .try_alloc(Layout::from_size_align(128 * size_of::<usize>(), 1).unwrap())
+        .is_err());
+    heap.dealloc_ptr(addr, layout);
+    #[cfg(feature = "stats")]
+    assert_eq!(heap.allocated_bytes(), 0);
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_heap_min_order() {
+    const MIN_ORDER: usize = 6;
+    let mut heap = Heap::<32>::with_min_order(MIN_ORDER);
+    assert_eq!(heap.min_order(), MIN_ORDER);
+
+    let layout = Layout::from_size_align(1 << 10, 1 << 10).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    let begin = space as usize;
+    // the partial 64-byte blocks at both ends are not added
+    let added = unsafe { heap.add_to_heap_usable(begin + 1, begin + (1 << 10) - 1) };
+    assert_eq!(added, (1 << 10) - 2 * (1 << MIN_ORDER));
+
+    let small = Layout::from_size_align(1, 1).unwrap();
+    let addrs: std::vec::Vec<_> = (0..14).map(|_| heap.alloc(small).unwrap()).collect();
+    for addr in addrs.iter() {
+        assert_eq!(addr.as_ptr() as usize % (1 << MIN_ORDER), 0);
+    }
+    assert_eq!(heap.allocated_bytes(), 14 << MIN_ORDER);
+    assert!(heap.alloc(small).is_err());
+    for order in 0..MIN_ORDER {
+        assert_eq!(heap.free_list_len(order), 0);
+    }
+
+    for addr in addrs {
+        heap.dealloc(addr, small);
+    }
+    assert_eq!(heap.allocated_bytes(), 0);
+    assert_eq!(heap.available_bytes(), added);
+    unsafe { std::alloc::dealloc(space, layout) };
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_heap_display() {
+    let mut heap = Heap::<32>::new();
+    assert_eq!(
+        format!("{}", heap),
+        "user 0 B, allocated 0 B, total 0 B, fragmentation 0.00%"
+    );
+
+    let layout = Layout::from_size_align(4096, 4096).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    unsafe { heap.init(space as usize, 4096) };
+    let small = Layout::from_size_align(1000, 1).unwrap();
+    let addr = heap.alloc(small).unwrap();
+    assert_eq!(
+        format!("{}", heap),
+        "user 1000 B, allocated 1.00 KiB, total 4.00 KiB, fragmentation 2.34%"
+    );
+    heap.dealloc(addr, small);
+
+    assert_eq!(format!("{}", crate::HumanBytes(3 << 29)), "1.50 GiB");
+    assert_eq!(format!("{}", crate::HumanBytes(1023 << 20)), "1023.00 MiB");
+    assert_eq!(format!("{}", crate::HumanBytes(1 << 30)), "1.00 GiB");
+    #[cfg(target_pointer_width = "32")]
+    assert_eq!(format!("{}", crate::HumanBytes(usize::MAX)), "3.99 GiB");
+    #[cfg(target_pointer_width = "64")]
+    {
+        assert_eq!(format!("{}", crate::HumanBytes(5 << 40)), "5.00 TiB");
+        assert_eq!(format!("{}", crate::HumanBytes(usize::MAX)), "15.99 EiB");
+    }
+    unsafe { std::alloc::dealloc(space, layout) };
+}
+
+#[test]
+#[cfg(feature = "paranoid")]
+#[should_panic(expected = "was allocated with size 4 but deallocated with size 8")]
+fn test_frame_allocator_dealloc_wrong_count() {
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(0, 1024);
+    let addr = frame.alloc(3).unwrap();
+    // counts that round to the same size are fine
+    frame.dealloc(addr, 4);
+    let addr = frame.alloc(3).unwrap();
+    frame.dealloc(addr, 5);
+}
+
+#[test]
+fn test_frame_allocator_alloc_contiguous() {
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(0, 16);
+
+    let range = frame.alloc_contiguous(5).unwrap();
+    assert_eq!(range.len(), 5);
+    assert_eq!(frame.allocated_frames(), 8);
+    frame.dealloc(range.start, range.len());
+    assert_eq!(frame.allocated_frames(), 0);
+
+    assert_eq!(frame.alloc_contiguous(16), Some(0..16));
+    assert_eq!(frame.alloc_contiguous(1), None);
+}
+
+#[test]
+fn test_locked_heap_custom_lock() {
+    use core::cell::Cell;
+
+    /// A lock for a single thread, which panics on reentrance
+    struct CellMutex(Cell<bool>);
+
+    unsafe impl lock_api::RawMutex for CellMutex {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const INIT: Self = CellMutex(Cell::new(false));
+        type GuardMarker = lock_api::GuardNoSend;
+
+        fn lock(&self) {
+            assert!(self.try_lock(), "already locked");
+        }
+
+        fn try_lock(&self) -> bool {
+            !self.0.replace(true)
+        }
+
+        unsafe fn unlock(&self) {
+            self.0.set(false);
+        }
+    }
+
+    let heap = LockedHeap::<32, CellMutex>::new();
+    let space: [usize; 100] = [0; 100];
+    unsafe { heap.init(space.as_ptr() as usize, 100 * size_of::<usize>()) };
+
+    let layout = Layout::from_size_align(size_of::<usize>(), 1).unwrap();
+    let addr = unsafe { heap.alloc(layout) };
+    assert!(!addr.is_null());
+    #[cfg(feature = "stats")]
+    assert_eq!(heap.lock().allocated_bytes(), size_of::<usize>());
+    unsafe { heap.dealloc(addr, layout) };
+    #[cfg(feature = "stats")]
+    assert_eq!(heap.allocated_bytes(), 0);
+}
+
+#[test]
+fn test_heap_contains() {
+    let mut heap = Heap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    let begin = space.as_ptr() as usize;
+    let word = size_of::<usize>();
+    assert!(!heap.contains(begin));
+
+    unsafe {
+        heap.add_to_heap(begin, begin + 40 * word);
+        heap.add_to_heap(begin + 60 * word, begin + 100 * word);
+    }
+    assert!(heap.contains(begin));
+    assert!(heap.contains(begin + 39 * word + 1));
+    assert!(!heap.contains(begin + 40 * word));
+    assert!(!heap.contains(begin + 50 * word));
+    assert!(heap.contains(begin + 60 * word));
+    assert!(heap.contains(begin + 100 * word - 1));
+    assert!(!heap.contains(begin + 100 * word));
+    assert!(!heap.contains(begin - 1));
+
+    // a contiguous region is coalesced with both neighbours
+    unsafe { heap.add_to_heap(begin + 40 * word, begin + 60 * word) };
+    assert!(heap.contains(begin + 50 * word));
+    assert_eq!(heap.regions.iter().count(), 1);
+}
+
+#[test]
+fn test_regions_overflow() {
+    let mut regions = crate::region::Regions::new();
+    // regions with growing gaps between them
+    for i in 0..20 {
+        regions.insert(i * i * 100, i * i * 100 + 10);
+    }
+    assert_eq!(regions.iter().count(), 16);
+    for i in 0..20 {
+        assert!(regions.contains(i * i * 100));
+        assert!(regions.contains(i * i * 100 + 9));
+    }
+    // only the smallest gaps were merged
+    assert!(!regions.contains(19 * 19 * 100 - 1));
+    let ranges: std::vec::Vec<_> = regions.iter().collect();
+    for pair in ranges.windows(2) {
+        assert!(pair[0].1 < pair[1].0);
+    }
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_heap_fragmentation() {
+    let mut heap = Heap::<32>::new();
+    assert_eq!(heap.fragmentation(), 0.0);
+
+    let layout = Layout::from_size_align(4096, 4096).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    unsafe { heap.init(space as usize, 4096) };
+
+    let exact = Layout::from_size_align(1024, 1).unwrap();
+    let addr = heap.alloc(exact).unwrap();
+    assert_eq!(heap.fragmentation(), 0.0);
+
+    let awkward = Layout::from_size_align(1000, 1).unwrap();
+    let other = heap.alloc(awkward).unwrap();
+    let fragmentation = heap.fragmentation();
+    assert!(fragmentation > 0.0 && fragmentation < 1.0);
+    assert_eq!(fragmentation, 24.0 / 2048.0);
+
+    heap.dealloc(other, awkward);
+    heap.dealloc(addr, exact);
+    assert_eq!(heap.fragmentation(), 0.0);
+    unsafe { std::alloc::dealloc(space, layout) };
+}
+
+#[test]
+fn test_frame_allocator_reset() {
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(0, 1024);
+    frame.alloc(100).unwrap();
+    frame.alloc(1).unwrap();
+
+    frame.reset();
+    assert_eq!(frame.total_frames(), 0);
+    assert_eq!(frame.allocated_frames(), 0);
+    assert_eq!(frame.free_ranges().count(), 0);
+    assert!(frame.alloc(1).is_none());
+
+    frame.add_frame(1024, 2048);
+    assert_eq!(frame.available_frames(), 1024);
+    assert_eq!(frame.alloc(1024), Some(1024));
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_heap_zero_size() {
+    let mut heap = Heap::<32>::new();
+    let zero = Layout::from_size_align(0, 64).unwrap();
+    // no memory is needed for zero-sized allocations
+    let addr = heap.alloc(zero).unwrap();
+    assert_eq!(addr.as_ptr() as usize % 64, 0);
+    heap.dealloc(addr, zero);
+
+    let space: [usize; 100] = [0; 100];
+    unsafe { heap.init(space.as_ptr() as usize, 100 * size_of::<usize>()) };
+    for _ in 0..1000 {
+        let addr = heap.alloc(zero).unwrap();
+        assert_eq!(heap.allocated_bytes(), 0);
+        heap.dealloc(addr, zero);
+    }
+    assert_eq!(heap.allocated_bytes(), 0);
+    assert_eq!(heap.stats_alloc_user(), 0);
+    assert_eq!(heap.available_bytes(), 100 * size_of::<usize>());
+
+    // growing a zero-sized allocation moves it into the heap
+    let locked = LockedHeap::<32>::new();
+    unsafe {
+        locked.init(space.as_ptr() as usize, 100 * size_of::<usize>());
+        let addr = locked.alloc(zero);
+        let grown = locked.realloc(addr, zero, 8);
+        assert!(locked.lock().contains(grown as usize));
+        locked.dealloc(grown, Layout::from_size_align(8, 64).unwrap());
+    }
+}
+
+#[test]
+fn test_heap_remove_from_heap() {
+    const NUM_ORDERS: usize = 6;
+    let top = 1 << (NUM_ORDERS - 1);
+    let layout = Layout::from_size_align(4 * top, top).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    let begin = space as usize;
+
+    let mut heap = Heap::<NUM_ORDERS>::new();
+    unsafe { heap.add_to_heap(begin, begin + 4 * top) };
+    assert_eq!(heap.free_regions().count(), 4);
+
+    // split two top-order blocks, then free everything so they coalesce again
+    let small = Layout::from_size_align(size_of::<usize>(), 1).unwrap();
+    let blocks: std::vec::Vec<_> = (0..(top / size_of::<usize>() + 1))
+        .map(|_| heap.alloc(small).unwrap())
+        .collect();
+    assert_eq!(heap.free_regions().count(), 2);
+    for block in blocks {
+        heap.dealloc(block, small);
+    }
+    let mut regions: std::vec::Vec<_> = heap.free_regions().collect();
+    regions.sort();
+    assert_eq!(
+        regions,
+        (0..4)
+            .map(|i| (begin + i * top, top))
+            .collect::<std::vec::Vec<_>>()
+    );
+
+    // release two blocks
+    assert!(heap.remove_from_heap(begin, top));
+    assert!(heap.remove_from_heap(begin + 3 * top, top));
+    assert!(!heap.remove_from_heap(begin, top));
+    assert!(!heap.remove_from_heap(begin + top, 3));
+    #[cfg(feature = "stats")]
+    assert_eq!(heap.total_bytes(), 2 * top);
+    assert_eq!(heap.free_regions().count(), 2);
+    assert!(!heap.contains(begin));
+    assert!(heap.contains(begin + top));
+    assert!(!heap.contains(begin + 3 * top));
+
+    // the rest of the heap still works
+    let big = Layout::from_size_align(top, 1).unwrap();
+    let a = heap.alloc(big).unwrap();
+    let b = heap.alloc(big).unwrap();
+    assert!(heap.alloc(small).is_err());
+    heap.dealloc(a, big);
+    heap.dealloc(b, big);
+    unsafe { std::alloc::dealloc(space, layout) };
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_locked_heap_stats() {
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    let heap = LockedHeap::<32>::new();
+    let space = std::vec![0usize; 4096];
+    let total = space.len() * size_of::<usize>();
+    unsafe { heap.init(space.as_ptr() as usize, total) };
+    assert_eq!(
+        heap.stats(),
+        crate::HeapStats {
+            user: 0,
+            allocated: 0,
+            total,
+        }
+    );
+
+    let done = AtomicBool::new(false);
+    // the reader and the writers all start at once
+    let start = std::sync::Barrier::new(5);
+    std::thread::scope(|scope| {
+        let writers: std::vec::Vec<_> = (1..5)
+            .map(|i| {
+                let (heap, start) = (&heap, &start);
+                scope.spawn(move || {
+                    start.wait();
+                    let layout = Layout::from_size_align(i * 24, 8).unwrap();
+                    for _ in 0..1000 {
+                        let addr = unsafe { heap.alloc(layout) };
+                        assert!(!addr.is_null());
+                        unsafe { heap.dealloc(addr, layout) };
+                    }
+                })
+            })
+            .collect();
+        scope.spawn(|| {
+            start.wait();
+            loop {
+                let stopping = done.load(Ordering::SeqCst);
+                let stats = heap.stats();
+                assert!(stats.user <= stats.allocated);
+                assert!(stats.allocated <= stats.total);
+                assert_eq!(stats.total, total);
+                if stopping {
+                    break;
+                }
+            }
+        });
+        // stop the reader once the writers are done
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        done.store(true, Ordering::SeqCst);
+    });
+    assert_eq!(heap.stats().allocated, 0);
+}
+
+#[test]
+fn test_frame_allocator_handle() {
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(64, 128);
+
+    let three = frame.alloc_handle(3).unwrap();
+    assert_eq!(three.size(), 4);
+    assert_eq!(three.start() % 4, 0);
+    let five = frame.alloc_handle(5).unwrap();
+    assert_eq!(five.size(), 8);
+    assert_eq!(frame.allocated_frames(), 12);
+
+    frame.dealloc_handle(three);
+    frame.dealloc_handle(five);
+    assert_eq!(frame.allocated_frames(), 0);
+    assert_eq!(frame.alloc(64), Some(64));
+}
+
+#[test]
+fn test_heap_fit_policy() {
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(64 * word, 64 * word).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    let begin = space as usize;
+    let small = Layout::from_size_align(word, 1).unwrap();
+
+    for prefer_largest in [false, true] {
+        let mut heap = Heap::<32>::new();
+        heap.set_prefer_largest(prefer_largest);
+        // free blocks of 4 and 32 words
+        unsafe {
+            heap.add_to_heap(begin, begin + 4 * word);
+            heap.add_to_heap(begin + 32 * word, begin + 64 * word);
+        }
+
+        let addr = heap.alloc(small).unwrap().as_ptr() as usize;
+        if prefer_largest {
+            assert!(addr >= begin + 32 * word);
+            assert_eq!(heap.free_list_len((4 * word).trailing_zeros() as usize), 2);
+        } else {
+            assert!(addr < begin + 4 * word);
+            assert_eq!(heap.free_list_len((32 * word).trailing_zeros() as usize), 1);
+        }
+    }
+    unsafe { std::alloc::dealloc(space, layout) };
+}
+
+#[test]
+fn test_linked_list_remove() {
+    let mut values = [0usize; 4];
+    let ptrs: std::vec::Vec<*mut usize> = values.iter_mut().map(|v| v as *mut usize).collect();
+    let mut list = linked_list::LinkedList::new();
+    unsafe {
+        for &ptr in ptrs.iter() {
+            list.push(ptr);
+        }
+    }
+    // the list is now 3, 2, 1, 0
+
+    // middle
+    assert!(list.remove(ptrs[2]));
+    assert!(!list.remove(ptrs[2]));
+    assert_eq!(
+        list.iter().collect::<std::vec::Vec<_>>(),
+        [ptrs[3], ptrs[1], ptrs[0]]
+    );
+
+    // head
+    assert!(list.remove(ptrs[3]));
+    assert_eq!(
+        list.iter().collect::<std::vec::Vec<_>>(),
+        [ptrs[1], ptrs[0]]
+    );
+
+    // tail
+    assert!(list.remove(ptrs[0]));
+    assert_eq!(list.iter().collect::<std::vec::Vec<_>>(), [ptrs[1]]);
+    assert_eq!(list.len(), 1);
+
+    assert!(list.remove(ptrs[1]));
+    assert!(list.is_empty());
+    assert!(!list.remove(ptrs[1]));
+}
+
+// zeroing the freed 8GiB block would actually back it with memory
+#[cfg(all(
+    target_os = "linux",
+    target_pointer_width = "64",
+    not(feature = "zero_on_free")
+))]
+#[test]
+fn test_heap_large_order() {
+    extern "C" {
+        fn mmap(addr: *mut u8, len: usize, prot: i32, flags: i32, fd: i32, off: i64) -> *mut u8;
+        fn munmap(addr: *mut u8, len: usize) -> i32;
+    }
+    const PROT_READ_WRITE: i32 = 0x1 | 0x2;
+    // MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE, pages are only backed once touched
+    const MAP_FLAGS: i32 = 0x2 | 0x20 | 0x4000;
+
+    // reserve 32GiB of address space and pick a 12GiB region aligned to 8GiB inside it
+    let reserved = 1usize << 35;
+    let space = unsafe {
+        mmap(
+            core::ptr::null_mut(),
+            reserved,
+            PROT_READ_WRITE,
+            MAP_FLAGS,
+            -1,
+            0,
+        )
+    };
+    assert_ne!(space as isize, -1);
+    let begin = (space as usize + (1 << 33) - 1) & !((1 << 33) - 1);
+    let end = begin + (1 << 33) + (1 << 32);
+
+    let mut heap = Heap::<40>::new();
+    unsafe { heap.add_to_heap(begin, end) };
+    assert_eq!(heap.max_contiguous_alloc(), 1 << 33);
+    assert_eq!(heap.free_list_len(33), 1);
+    assert_eq!(heap.free_list_len(32), 1);
+    let layout = Layout::from_size_align(1 << 33, 1).unwrap();
+    let addr = heap.alloc(layout).unwrap();
+    assert_eq!(addr.as_ptr() as usize, begin);
+    heap.dealloc(addr, layout);
+    assert_eq!(heap.free_list_len(33), 1);
+
+    // with the default order, the region is cut into 2GiB blocks
+    let mut heap = Heap::<32>::new();
+    unsafe { heap.add_to_heap(begin, end) };
+    assert_eq!(heap.max_contiguous_alloc(), 1 << 31);
+    assert_eq!(heap.free_list_len(31), 6);
+    assert!(heap.alloc(layout).is_err());
+
+    unsafe { munmap(space, reserved) };
+}
+
+#[test]
+#[should_panic(expected = "too large for a heap with max order 7")]
+fn test_heap_dealloc_too_large() {
+    let mut heap = Heap::<8>::new();
+    let layout = Layout::from_size_align(1 << 8, 1).unwrap();
+    heap.dealloc(NonNull::dangling(), layout);
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "is not aligned to its block size")]
+fn test_heap_dealloc_misaligned() {
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(4 * word, 4 * word).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    let mut heap = Heap::<32>::new();
+    unsafe {
+        heap.add_to_heap(space as usize, space as usize + 4 * word);
+    }
+    let addr = heap.alloc(layout).unwrap();
+    // free a pointer one word into the block, which is not aligned to two words
+    let ptr = NonNull::new(unsafe { addr.as_ptr().add(word) }).unwrap();
+    heap.dealloc(ptr, Layout::from_size_align(2 * word, 1).unwrap());
+}
+
+#[test]
+fn test_heap_split_merge_count() {
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(16 * word, 16 * word).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    let mut heap = Heap::<32>::new();
+    unsafe {
+        heap.add_to_heap(space as usize, space as usize + 16 * word);
+    }
+    let one = Layout::from_size_align(word, 1).unwrap();
+
+    // 16 words are split down to 1 word: 16 -> 8 -> 4 -> 2 -> 1
+    let a = heap.alloc(one).unwrap();
+    assert_eq!((heap.split_count(), heap.merge_count()), (4, 0));
+    // the buddy of the first word is already free
+    let b = heap.alloc(one).unwrap();
+    assert_eq!((heap.split_count(), heap.merge_count()), (4, 0));
+    let c = heap
+        .alloc(Layout::from_size_align(2 * word, 1).unwrap())
+        .unwrap();
+    assert_eq!((heap.split_count(), heap.merge_count()), (4, 0));
+
+    // `a` can not merge while `b` is allocated
+    heap.dealloc(a, one);
+    assert_eq!((heap.split_count(), heap.merge_count()), (4, 0));
+    // `b` merges with `a`, which can not merge with `c`
+    heap.dealloc(b, one);
+    assert_eq!((heap.split_count(), heap.merge_count()), (4, 1));
+    // `c` merges all the way back to 16 words
+    heap.dealloc(c, Layout::from_size_align(2 * word, 1).unwrap());
+    assert_eq!((heap.split_count(), heap.merge_count()), (4, 4));
+    assert!(format!("{:?}", heap).contains("splits: 4, merges: 4"));
+
+    unsafe { std::alloc::dealloc(space, layout) };
+}
+
+#[test]
+fn test_frame_allocator_clone() {
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(16, 32);
+    assert_eq!(frame.alloc(4), Some(16));
+
+    let mut snapshot = frame.clone();
+    assert_eq!(frame.alloc(4), Some(20));
+    assert_eq!(frame.allocated_frames(), 8);
+    assert_eq!(snapshot.allocated_frames(), 4);
+
+    // the snapshot still sees the frames allocated from the original as free
+    assert_eq!(snapshot.alloc(4), Some(20));
+    assert_eq!(snapshot.alloc(8), Some(24));
+    assert_eq!(frame.alloc(8), Some(24));
+    snapshot.dealloc(16, 4);
+    assert_eq!(snapshot.allocated_frames(), 12);
+    assert_eq!(frame.allocated_frames(), 16);
+    assert_eq!(frame.alloc(1), None);
+}
+
+#[test]
+fn test_frame_allocator_add_from_zero() {
+    let mut frame = FrameAllocator::<8>::new();
+    frame.add_frame(0, 1);
+    assert_eq!(frame.total_frames(), 1);
+    assert_eq!(frame.alloc(1), Some(0));
+    assert_eq!(frame.alloc(1), None);
+
+    // frame 0 starts a block of the max order rather than a fixed size
+    let mut frame = FrameAllocator::<8>::new();
+    frame.add_frame(0, 1000);
+    assert_eq!(frame.total_frames(), 1000);
+    for i in 0..7 {
+        assert_eq!(frame.alloc(128), Some(i * 128));
+    }
+    assert_eq!(frame.alloc(64), Some(896));
+    assert_eq!(frame.alloc(32), Some(960));
+    assert_eq!(frame.alloc(8), Some(992));
+    assert_eq!(frame.alloc(1), None);
+
+    let mut frame = FrameAllocator::<33>::new();
+    frame.add_frame(0, 1 << 32);
+    assert_eq!(frame.alloc(1 << 32), Some(0));
+}
+
+#[test]
+fn test_frame_allocator_add_empty() {
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(0, 0);
+    frame.add_frame(5, 5);
+    frame.insert(7..7);
+    assert_eq!(frame.total_frames(), 0);
+    assert_eq!(frame.free_ranges().count(), 0);
+    assert_eq!(frame.alloc(1), None);
+}
+
+#[cfg(feature = "trace")]
+#[test]
+fn test_heap_leaks() {
+    let mut heap = Heap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    unsafe {
+        heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize);
+    }
+    assert_eq!(heap.leaks().count(), 0);
+
+    let small = Layout::from_size_align(1, 1).unwrap();
+    let large = Layout::from_size_align(100, 1).unwrap();
+    let a = heap.alloc(small).unwrap();
+    let b = heap.alloc(large).unwrap();
+    let mut leaks: std::vec::Vec<_> = heap.leaks().collect();
+    leaks.sort();
+    let mut expected = [(a.as_ptr() as usize, 1), (b.as_ptr() as usize, 100)];
+    expected.sort();
+    assert_eq!(leaks, expected);
+
+    heap.dealloc(a, small);
+    assert_eq!(
+        heap.leaks().collect::<std::vec::Vec<_>>(),
+        [(b.as_ptr() as usize, 100)]
+    );
+    heap.dealloc(b, large);
+    assert_eq!(heap.leaks().count(), 0);
+}
+
+#[cfg(feature = "trace")]
+#[test]
+fn test_heap_leaks_kept_across_allocations() {
+    let mut heap = Heap::<32, 4>::new();
+    let space: [usize; 100] = [0; 100];
+    unsafe {
+        heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize);
+    }
+    let layout = Layout::from_size_align(1, 1).unwrap();
+
+    // a long-lived allocation is not pushed out by later allocations which are freed
+    let leak = heap.alloc(layout).unwrap();
+    for _ in 0..100 {
+        let ptr = heap.alloc(layout).unwrap();
+        heap.dealloc(ptr, layout);
+    }
+    assert_eq!(
+        heap.leaks().collect::<std::vec::Vec<_>>(),
+        [(leak.as_ptr() as usize, 1)]
+    );
+
+    // once every slot is used, new allocations take the place of older ones
+    let ptrs: std::vec::Vec<_> = (0..4).map(|_| heap.alloc(layout).unwrap()).collect();
+    let leaks: std::vec::Vec<_> = heap.leaks().map(|(ptr, _)| ptr).collect();
+    assert_eq!(leaks.len(), 4);
+    assert!(leaks.contains(&(ptrs[3].as_ptr() as usize)));
+    assert!(leaks.iter().all(
+        |&ptr| ptr == leak.as_ptr() as usize || ptrs.iter().any(|p| p.as_ptr() as usize == ptr)
+    ));
+    for ptr in ptrs {
+        heap.dealloc(ptr, layout);
+    }
+    heap.dealloc(leak, layout);
+    assert_eq!(heap.leaks().count(), 0);
+}
+
+#[test]
+fn test_locked_frame_allocator_methods() {
+    let frame = LockedFrameAllocator::<32>::new();
+    assert_eq!(frame.alloc(1), None);
+
+    frame.add_frame(8, 16);
+    frame.insert(16..32);
+    assert_eq!(frame.lock().total_frames(), 24);
+
+    assert_eq!(frame.alloc(8), Some(8));
+    assert_eq!(
+        frame.alloc_aligned(Layout::from_size_align(1, 16).unwrap()),
+        Some(16)
+    );
+    assert_eq!(frame.alloc(16), None);
+    frame.dealloc(8, 8);
+    frame
+        .lock()
+        .dealloc_aligned(16, Layout::from_size_align(1, 16).unwrap());
+    assert_eq!(frame.lock().allocated_frames(), 0);
+    assert_eq!(frame.alloc(16), Some(16));
+}
+
+#[test]
+fn test_heap_deterministic_addresses() {
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(16 * word, 16 * word).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    let begin = space as usize;
+    let one = Layout::from_size_align(word, 1).unwrap();
+    let two = Layout::from_size_align(2 * word, 1).unwrap();
+    let four = Layout::from_size_align(4 * word, 1).unwrap();
+
+    // run the same sequence twice to check that it is reproducible
+    for _ in 0..2 {
+        let mut heap = Heap::<32>::new();
+        unsafe {
+            heap.add_to_heap(begin, begin + 16 * word);
+        }
+
+        // split blocks hand out their lower half first
+        let a = heap.alloc(one).unwrap();
+        assert_eq!(a.as_ptr() as usize, begin);
+        let b = heap.alloc(one).unwrap();
+        assert_eq!(b.as_ptr() as usize, begin + word);
+        let c = heap.alloc(two).unwrap();
+        assert_eq!(c.as_ptr() as usize, begin + 2 * word);
+        let e = heap.alloc(four).unwrap();
+        assert_eq!(e.as_ptr() as usize, begin + 4 * word);
+        let f = heap.alloc(four).unwrap();
+        assert_eq!(f.as_ptr() as usize, begin + 8 * word);
+        let g = heap.alloc(four).unwrap();
+        assert_eq!(g.as_ptr() as usize, begin + 12 * word);
+
+        // freed blocks are reused in LIFO order
+        heap.dealloc(e, four);
+        heap.dealloc(g, four);
+        assert_eq!(heap.alloc(four).unwrap(), g);
+        assert_eq!(heap.alloc(four).unwrap(), e);
+        heap.dealloc(a, one);
+        assert_eq!(heap.alloc(one).unwrap(), a);
+    }
+    unsafe { std::alloc::dealloc(space, layout) };
+}
+
+#[test]
+fn test_heap_extend() {
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(16 * word, 16 * word).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    let begin = space as usize;
+    let whole = Layout::from_size_align(16 * word, 1).unwrap();
+
+    // adding the second half separately keeps it apart from the first half
+    let mut heap = Heap::<32>::new();
+    unsafe {
+        heap.add_to_heap(begin, begin + 8 * word);
+        heap.add_to_heap(begin + 8 * word, begin + 16 * word);
+    }
+    assert!(heap.alloc(whole).is_err());
+
+    let mut heap = Heap::<32>::new();
+    unsafe {
+        heap.add_to_heap(begin, begin + 8 * word);
+        heap.extend(begin + 8 * word, begin + 16 * word);
+    }
+    #[cfg(feature = "stats")]
+    assert_eq!(heap.total_bytes(), 16 * word);
+    assert_eq!(heap.max_contiguous_alloc(), 16 * word);
+    let addr = heap.alloc(whole).unwrap();
+    assert_eq!(addr.as_ptr() as usize, begin);
+    heap.dealloc(addr, whole);
+
+    // extending downwards merges as well
+    let mut heap = Heap::<32>::new();
+    unsafe {
+        heap.add_to_heap(begin + 8 * word, begin + 16 * word);
+        heap.extend(begin, begin + 8 * word);
+    }
+    assert_eq!(heap.max_contiguous_alloc(), 16 * word);
+    assert_eq!(heap.free_list_len((16 * word).trailing_zeros() as usize), 1);
+
+    unsafe { std::alloc::dealloc(space, layout) };
+}
+
+#[test]
+fn test_heap_alloc_with_size() {
+    let word = size_of::<usize>();
+    let mut heap = Heap::<32>::with_min_order(4);
+    let space: [usize; 1000] = [0; 1000];
+    unsafe {
+        heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(1000) as usize);
+    }
+
+    for (size, align, expected) in [
+        (1, 1, 16),
+        (17, 1, 32),
+        (64, 8, 64),
+        (100, 1, 128),
+        (8, 256, 16),
+        (2 * word, word, 16),
+        (0, 64, 0),
+    ] {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        let (ptr, block) = heap.alloc_with_size(layout).unwrap();
+        assert_eq!(block, expected, "{:?}", layout);
+        assert_eq!(ptr.as_ptr() as usize % align, 0);
+        if size != 0 {
+            #[cfg(feature = "stats")]
+            assert_eq!(heap.stats_alloc_actual(), expected);
+        }
+        heap.dealloc(ptr, layout);
+        #[cfg(feature = "stats")]
+        assert_eq!(heap.stats_alloc_actual(), 0);
+    }
+}
+
+#[test]
+fn test_heap_alloc_max_order_boundary() {
+    let layout = Layout::from_size_align(1 << 7, 1 << 7).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    let mut heap = Heap::<8>::new();
+    unsafe {
+        heap.add_to_heap(space as usize, space as usize + (1 << 7));
+    }
+
+    // one byte more than the largest block fails cleanly, even with the block free
+    assert!(heap
+        .alloc(Layout::from_size_align((1 << 7) + 1, 1).unwrap())
+        .is_err());
+    assert!(heap
+        .alloc(Layout::from_size_align(1, 1 << 8).unwrap())
+        .is_err());
+    assert!(heap
+        .alloc(Layout::from_size_align(isize::MAX as usize, 1).unwrap())
+        .is_err());
+    #[cfg(feature = "stats")]
+    assert_eq!(heap.stats_alloc_actual(), 0);
+
+    let max = Layout::from_size_align(1 << 7, 1).unwrap();
+    let addr = heap.alloc(max).unwrap();
+    assert_eq!(addr.as_ptr(), space);
+    heap.dealloc(addr, max);
+    assert_eq!(heap.free_list_len(7), 1);
+
+    unsafe { std::alloc::dealloc(space, layout) };
+}
+
+#[test]
+fn test_heap_alloc_error() {
+    let mut heap = Heap::<32>::new();
+    let layout = Layout::from_size_align(1, 1).unwrap();
+    let empty = AllocError::OutOfMemory {
+        requested: 1,
+        largest_free: 0,
+    };
+    assert_eq!(heap.alloc(layout), Err(empty));
+    assert_eq!(heap.alloc_with_size(layout), Err(empty));
+    assert_eq!(LockedHeap::<32>::new().try_alloc(layout), Err(empty));
+    assert_eq!(
+        format!("{}", empty),
+        "memory allocation of 1 bytes failed, the largest free block has 0 bytes"
+    );
+
+    // blocks of up to 128 bytes
+    let space: [usize; 64] = [0; 64];
+    let mut heap = Heap::<8>::new();
+    unsafe { heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(64) as usize) };
+    let block = Layout::from_size_align(128, 1).unwrap();
+    let mut blocks = std::vec::Vec::new();
+    while let Ok(ptr) = heap.alloc(block) {
+        blocks.push(ptr);
+    }
+    assert!(matches!(
+        heap.alloc(block),
+        Err(AllocError::OutOfMemory { requested: 128, .. })
+    ));
+    heap.dealloc(blocks.pop().unwrap(), block);
+    assert_eq!(heap.alloc(block).map(|_| ()), Ok(()));
+
+    // requests which could not be satisfied even by an empty heap
+    let too_large = AllocError::SizeTooLarge {
+        requested: 129,
+        max: 128,
+    };
+    assert_eq!(
+        heap.alloc(Layout::from_size_align(129, 1).unwrap()),
+        Err(too_large)
+    );
+    assert_eq!(
+        heap.alloc(Layout::from_size_align(8, 256).unwrap()),
+        Err(AllocError::SizeTooLarge {
+            requested: 256,
+            max: 128
+        })
+    );
+    assert_eq!(
+        format!("{}", too_large),
+        "memory allocation of 129 bytes can never succeed, the largest block has 128 bytes"
+    );
+}
+
+#[test]
+fn test_frame_allocator_is_allocated() {
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(16, 32);
+    let start = frame.alloc(4).unwrap();
+    assert_eq!(start, 16);
+
+    for i in 16..20 {
+        assert!(frame.is_allocated(i));
+        assert!(!frame.is_free(i));
+    }
+    for i in 20..32 {
+        assert!(frame.is_free(i));
+        assert!(!frame.is_allocated(i));
+    }
+    // frames that were never added are not free
+    for i in [0, 15, 32, usize::MAX] {
+        assert!(!frame.is_free(i));
+        assert!(frame.is_allocated(i));
+    }
+
+    frame.dealloc(start, 4);
+    assert!((16..32).all(|i| frame.is_free(i)));
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_heap_alloc_large_alignment() {
+    use core::cmp::max;
+
+    let page = Layout::from_size_align(4096, 4096).unwrap();
+    let pages = Layout::from_size_align(2 * 4096, 2 * 4096).unwrap();
+    let space = unsafe { std::alloc::alloc(pages) };
+    let mut heap = Heap::<32>::new();
+    unsafe {
+        heap.add_to_heap(space as usize, space as usize + 2 * 4096);
+    }
+
+    let layout = Layout::from_size_align(8, 4096).unwrap();
+    let size = max(8, size_of::<usize>());
+    let a = heap.alloc(layout).unwrap();
+    assert_eq!(a.as_ptr() as usize % 4096, 0);
+    assert_eq!(heap.stats_alloc_actual(), size);
+    let b = heap.alloc(layout).unwrap();
+    assert_eq!(b.as_ptr() as usize % 4096, 0);
+    assert_ne!(a, b);
+    assert_eq!(heap.stats_alloc_actual(), 2 * size);
+
+    // no page is left, but the rest of both pages is still free
+    assert!(heap.alloc(layout).is_err());
+    assert!(heap.alloc(page).is_err());
+    let half = Layout::from_size_align(2048, 1).unwrap();
+    let c = heap.alloc(half).unwrap();
+    let d = heap.alloc(half).unwrap();
+
+    heap.dealloc(a, layout);
+    heap.dealloc(b, layout);
+    heap.dealloc(c, half);
+    heap.dealloc(d, half);
+    assert_eq!(heap.stats_alloc_actual(), 0);
+    assert_eq!(heap.free_list_len(13), 1);
+
+    unsafe { std::alloc::dealloc(space, pages) };
+}
+
+#[test]
+fn test_frame_allocator_alloc_many() {
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(16, 32);
+
+    let mut frames = [0; 16];
+    assert_eq!(frame.alloc_many(10, &mut frames), 10);
+    assert_eq!(frame.allocated_frames(), 10);
+    let mut sorted = frames[..10].to_vec();
+    sorted.sort();
+    sorted.dedup();
+    assert_eq!(sorted.len(), 10);
+    assert!(sorted.iter().all(|&f| (16..32).contains(&f)));
+
+    // limited by the buffer, then by the free frames
+    let mut more = [0; 2];
+    assert_eq!(frame.alloc_many(5, &mut more), 2);
+    assert_eq!(frame.alloc_many(10, &mut frames[10..]), 4);
+    assert_eq!(frame.alloc_many(1, &mut more), 0);
+
+    for &f in frames[..14].iter().chain(more.iter()) {
+        frame.dealloc(f, 1);
+    }
+    assert_eq!(frame.allocated_frames(), 0);
+    assert_eq!(frame.alloc(16), Some(16));
+
+    let locked = LockedFrameAllocator::<32>::new();
+    locked.add_frame(16, 32);
+    assert_eq!(locked.alloc_many(3, &mut frames), 3);
+    assert_eq!(locked.lock().allocated_frames(), 3);
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_heap_dealloc_unchecked_size() {
+    let mut heap = Heap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    unsafe {
+        heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize);
+    }
+    let available = heap.available_bytes();
+
+    let mut blocks = std::vec::Vec::new();
+    for size in [1, 24, 100, 3] {
+        let layout = Layout::from_size_align(size, 1).unwrap();
+        let (ptr, block) = heap.alloc_with_size(layout).unwrap();
+        // the caller only keeps track of the class
+        blocks.push((ptr, block.trailing_zeros() as usize));
+    }
+    assert_eq!(heap.stats_alloc_user(), 128);
+
+    for (ptr, class) in blocks {
+        unsafe { heap.dealloc_unchecked_size(ptr, class) };
+    }
+    assert_eq!(heap.available_bytes(), available);
+    assert_eq!(heap.stats_alloc_actual(), 0);
+    assert_eq!(heap.stats_alloc_user(), 0);
+}
+
+#[cfg(feature = "paranoid")]
+#[test]
+#[should_panic(expected = "overlaps memory already added to the heap")]
+fn test_heap_add_overlapping() {
+    let mut heap = Heap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    let begin = space.as_ptr() as usize;
+    unsafe {
+        heap.add_to_heap(begin, begin + 50 * size_of::<usize>());
+        // adjacent regions are fine
+        heap.add_to_heap(
+            begin + 50 * size_of::<usize>(),
+            begin + 60 * size_of::<usize>(),
+        );
+        heap.add_to_heap(
+            begin + 40 * size_of::<usize>(),
+            begin + 100 * size_of::<usize>(),
+        );
+    }
+}
+
+#[cfg(feature = "paranoid")]
+#[test]
+fn test_heap_add_overlapping_many_regions() {
+    let word = size_of::<usize>();
+    let mut heap = Heap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    let begin = space.as_ptr() as usize;
+
+    // 20 regions of 2 words with a gap of 2 words after each, more than are tracked separately
+    for i in 0..20 {
+        let start = begin + 4 * i * word;
+        assert_eq!(
+            unsafe { heap.try_add_to_heap(start, start + 2 * word) },
+            Ok(2 * word)
+        );
+    }
+
+    // the free blocks still tell an overlap, but memory in a gap can be added
+    assert_eq!(
+        unsafe { heap.try_add_to_heap(begin + word, begin + 3 * word) },
+        Err("region overlaps memory already added to the heap")
+    );
+    assert_eq!(
+        unsafe { heap.try_add_to_heap(begin + 2 * word, begin + 4 * word) },
+        Ok(2 * word)
+    );
+    #[cfg(feature = "stats")]
+    assert_eq!(heap.total_bytes(), 42 * word);
+    assert_eq!(heap.verify(), Ok(()));
+}
+
+#[test]
+fn test_heap_iter_free() {
+    let mut heap = Heap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    let begin = space.as_ptr() as usize;
+    let end = space.as_ptr_range().end as usize;
+    unsafe {
+        heap.add_to_heap(begin + 3, end);
+    }
+
+    let blocks: std::vec::Vec<_> = heap.iter_free().collect();
+    #[cfg(feature = "stats")]
+    assert_eq!(
+        blocks.iter().map(|&(order, _)| 1 << order).sum::<usize>(),
+        heap.total_bytes()
+    );
+    for &(order, addr) in blocks.iter() {
+        assert_eq!(addr % (1 << order), 0);
+        assert!(addr >= begin && addr + (1 << order) <= end);
+    }
+    assert!(blocks.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+
+    let layout = Layout::from_size_align(64, 1).unwrap();
+    let addr = heap.alloc(layout).unwrap();
+    #[cfg(feature = "stats")]
+    assert_eq!(
+        heap.iter_free().map(|(order, _)| 1 << order).sum::<usize>(),
+        heap.available_bytes()
+    );
+    assert!(heap
+        .iter_free()
+        .all(|(_, block)| block != addr.as_ptr() as usize));
+    heap.dealloc(addr, layout);
+}
+
+#[test]
+fn test_static_frame_allocator() {
+    static FRAME: LockedFrameAllocator<32> = LockedFrameAllocator::new();
+    FRAME.add_frame(64, 128);
+    let frame = FRAME.alloc(16).unwrap();
+    assert!((64..128).contains(&frame));
+    FRAME.dealloc(frame, 16);
+    assert_eq!(FRAME.lock().allocated_frames(), 0);
+}
+
+#[test]
+fn test_heap_reserve_high_orders() {
+    let layout = Layout::from_size_align(1024, 512).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    let begin = space as usize;
+    let small = Layout::from_size_align(size_of::<usize>(), 1).unwrap();
+    let large = Layout::from_size_align(512, 1).unwrap();
+
+    for reserve in [0, 1] {
+        let mut heap = Heap::<10>::new();
+        heap.set_reserve_high_orders(reserve);
+        // free blocks of 512 and 256 bytes
+        unsafe {
+            heap.add_to_heap(begin, begin + 768);
+        }
+
+        // a storm of small allocations, one more than fit in the 256-byte block
+        let storm = 256 / size_of::<usize>() + 1;
+        let mut count = 0;
+        while count < storm && heap.alloc(small).is_ok() {
+            count += 1;
+        }
+        if reserve == 0 {
+            assert_eq!(count, storm);
+            assert!(heap.alloc(large).is_err());
+        } else {
+            assert_eq!(count, 256 / size_of::<usize>());
+            let addr = heap.alloc(large).unwrap();
+            assert_eq!(addr.as_ptr(), space);
+        }
+    }
+    unsafe { std::alloc::dealloc(space, layout) };
+}
+
+#[cfg(feature = "zero_on_free")]
+#[test]
+fn test_heap_zero_on_free() {
+    let mut heap = Heap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    unsafe {
+        heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize);
+    }
+    let small = Layout::from_size_align(8, 1).unwrap();
+    let large = Layout::from_size_align(64, 1).unwrap();
+
+    // fill the whole heap with a pattern, in blocks of different sizes
+    let mut blocks = std::vec::Vec::new();
+    while let Ok(addr) = heap.alloc(small) {
+        unsafe { core::ptr::write_bytes(addr.as_ptr(), 0xaa, 8) };
+        blocks.push(addr);
+    }
+    for addr in blocks {
+        heap.dealloc(addr, small);
+    }
+
+    // the blocks are merged again, links included, and read back as zeros
+    let addr = heap.alloc(large).unwrap();
+    let bytes = unsafe { core::slice::from_raw_parts(addr.as_ptr(), 64) };
+    assert!(bytes.iter().all(|&byte| byte == 0));
+    heap.dealloc(addr, large);
+}
+
+#[test]
+fn test_frame_allocator_alloc_max_order_boundary() {
+    let mut frame = FrameAllocator::<8>::new();
+    frame.add_frame(0, 1 << 8);
+
+    assert_eq!(frame.alloc((1 << 7) + 1), None);
+    assert_eq!(frame.alloc(usize::MAX), None);
+    assert_eq!(frame.alloc((1 << (usize::BITS - 1)) + 1), None);
+    assert!(frame.alloc_handle(usize::MAX).is_none());
+    assert_eq!(frame.alloc_contiguous(usize::MAX), None);
+    assert_eq!(frame.allocated_frames(), 0);
+
+    assert_eq!(frame.alloc(1 << 7), Some(0));
+    assert_eq!(frame.alloc(1 << 7), Some(1 << 7));
+    assert_eq!(frame.alloc(1), None);
+    frame.dealloc(0, 1 << 7);
+    frame.dealloc(1 << 7, 1 << 7);
+    assert_eq!(frame.free_ranges().count(), 2);
+
+    // the largest order of a 64-bit allocator
+    #[cfg(target_pointer_width = "64")]
+    {
+        let mut frame = FrameAllocator::<64>::new();
+        frame.add_frame(1 << 63, usize::MAX);
+        assert_eq!(frame.alloc(1 << 63), None);
+        assert_eq!(frame.alloc(1 << 62), Some(1 << 63));
+    }
+}
+
+#[cfg(feature = "bitmap")]
+#[test]
+fn test_heap_bitmap() {
+    use rand::{Rng, SeedableRng};
+
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(4096 * word, 4096 * word).unwrap();
+    let plain_space = unsafe { std::alloc::alloc(layout) } as usize;
+    let space = unsafe { std::alloc::alloc(layout) };
+    let begin = space as usize;
+    let end = begin + 4096 * word;
+
+    // the links of the free lists of a bitmap heap take two words
+    let min_order = (2 * word).trailing_zeros() as usize;
+    let mut plain = Heap::<32>::with_min_order(min_order);
+    let mut tracked = Heap::<32>::with_min_order(min_order);
+    unsafe {
+        plain.add_to_heap(plain_space, plain_space + 4096 * word);
+        tracked.add_to_heap(begin, begin + 2048 * word);
+    }
+    let bitmap = std::vec![0; Heap::<32>::bitmap_words(begin, end)].leak();
+    tracked.set_bitmap(bitmap, begin, end);
+    // memory added after the bitmap is tracked as well, and merged with the first half
+    unsafe {
+        tracked.extend(begin + 2048 * word, end);
+    }
+    assert_eq!(tracked.max_contiguous_alloc(), 4096 * word);
+
+    // both heaps make the same choices, the bitmap only saves searching for buddies
+    let offsets = |heap: &Heap<32>, base: usize| {
+        heap.iter_free()
+            .map(|(order, addr)| (order, addr - base))
+            .collect::<std::vec::Vec<_>>()
+    };
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+    let mut live = std::vec::Vec::new();
+    for _ in 0..2000 {
+        if live.is_empty() || rng.gen_bool(0.6) {
+            let layout = Layout::from_size_align(rng.gen_range(1..=64 * word), 1).unwrap();
+            match (plain.alloc(layout), tracked.alloc(layout)) {
+                (Ok(a), Ok(b)) => {
+                    assert_eq!(
+                        a.as_ptr() as usize - plain_space,
+                        b.as_ptr() as usize - begin
+                    );
+                    live.push((a, b, layout));
+                }
+                (Err(_), Err(_)) => {}
+                results => panic!("heaps diverged: {:?}", results),
+            }
+        } else {
+            let (a, b, layout) = live.swap_remove(rng.gen_range(0..live.len()));
+            plain.dealloc(a, layout);
+            tracked.dealloc(b, layout);
+        }
+        assert_eq!(offsets(&plain, plain_space), offsets(&tracked, begin));
+        assert_eq!(tracked.verify(), Ok(()));
+    }
+    assert_eq!(tracked.merge_count(), plain.merge_count() + 1);
+
+    for (_, b, layout) in live {
+        tracked.dealloc(b, layout);
+    }
+    assert_eq!(tracked.max_contiguous_alloc(), 4096 * word);
+    unsafe {
+        std::alloc::dealloc(plain_space as *mut u8, layout);
+        std::alloc::dealloc(space, layout);
+    }
+}
+
+#[cfg(feature = "bitmap")]
+#[test]
+#[should_panic(expected = "is too small")]
+fn test_heap_bitmap_too_small() {
+    let mut heap = Heap::<32>::with_min_order((2 * size_of::<usize>()).trailing_zeros() as usize);
+    let words = Heap::<32>::bitmap_words(0x1000, 0x2000);
+    heap.set_bitmap(std::vec![0; words - 1].leak(), 0x1000, 0x2000);
+}
+
+#[cfg(feature = "bitmap")]
+#[test]
+fn test_heap_bitmap_untracked_blocks() {
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(512 * word, 512 * word).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    let begin = space as usize;
+    let mid = begin + 256 * word;
+    let end = begin + 512 * word;
+
+    // only the first half is tracked, but the blocks of both halves share the free lists, and the
+    // halves are buddies
+    let mut heap = Heap::<32>::with_min_order((2 * word).trailing_zeros() as usize);
+    unsafe {
+        heap.add_to_heap(begin, mid);
+        heap.add_to_heap(mid, end);
+    }
+    heap.set_bitmap(
+        std::vec![0; Heap::<32>::bitmap_words(begin, mid)].leak(),
+        begin,
+        mid,
+    );
+
+    let small = Layout::from_size_align(2 * word, 1).unwrap();
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+    for round in 0..4 {
+        let mut blocks: std::vec::Vec<_> = core::iter::from_fn(|| heap.alloc(small).ok()).collect();
+        assert_eq!(blocks.len(), 256);
+        // both links are cleared once the memory has been freed
+        if cfg!(feature = "zero_on_free") && round > 0 {
+            for block in &blocks {
+                let links = unsafe { *(block.as_ptr() as *const [usize; 2]) };
+                assert_eq!(links, [0, 0]);
+            }
+        }
+        blocks.shuffle(&mut rng);
+        for block in blocks {
+            heap.dealloc(block, small);
+            assert_eq!(heap.verify(), Ok(()));
+        }
+        // the last merge takes a tracked block and its untracked buddy
+        assert_eq!(heap.max_contiguous_alloc(), 512 * word);
+    }
+    unsafe { std::alloc::dealloc(space, layout) };
+}
+
+#[cfg(feature = "bitmap")]
+#[test]
+fn test_heap_verify_bitmap() {
+    let word = size_of::<usize>();
+    let order = |size: usize| size.trailing_zeros() as usize;
+    let layout = Layout::from_size_align(64 * word, 64 * word).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    let begin = space as usize;
+    let end = begin + 64 * word;
+    let small = Layout::from_size_align(2 * word, 1).unwrap();
+    let new_heap = || {
+        let mut heap = Heap::<32>::with_min_order(order(2 * word));
+        unsafe { heap.add_to_heap(begin, end) };
+        heap.set_bitmap(
+            std::vec![0; Heap::<32>::bitmap_words(begin, end)].leak(),
+            begin,
+            end,
+        );
+        heap
+    };
+
+    let mut heap = new_heap();
+    let a = heap.alloc(small).unwrap();
+    assert_eq!(heap.verify(), Ok(()));
+    heap.dealloc(a, small);
+    assert_eq!(heap.verify(), Ok(()));
+
+    // a block pushed behind the back of the bitmap
+    let mut heap = new_heap();
+    let a = heap.alloc(small).unwrap();
+    unsafe { heap.free_list[order(2 * word)].push_linked(a.as_ptr() as *mut usize) };
+    assert_eq!(heap.verify(), Err("free block is not free in the bitmap"));
+
+    // a block which is free in the bitmap only
+    let mut heap = new_heap();
+    let a = heap.alloc(small).unwrap();
+    let bitmap = heap.bitmap.as_mut().unwrap();
+    bitmap.set(a.as_ptr() as usize, order(2 * word), true);
+    assert_eq!(
+        heap.verify(),
+        Err("bitmap has free blocks which are not in the free list")
+    );
+
+    // a free block inside a larger free block, both free in the bitmap
+    let mut heap = new_heap();
+    unsafe { heap.push_free(order(2 * word), begin + 8 * word) };
+    assert_eq!(heap.verify(), Err("free blocks overlap"));
+
+    // a broken link back
+    let heap = new_heap();
+    let head = heap.free_list[order(64 * word)].peek().unwrap();
+    unsafe { *head.add(1) = begin };
+    assert_eq!(
+        heap.verify(),
+        Err("free block does not link back to the previous one")
+    );
+    unsafe { std::alloc::dealloc(space, layout) };
+}
+
+#[cfg(feature = "bitmap")]
+#[test]
+#[should_panic(expected = "too small for the links")]
+fn test_heap_bitmap_min_order_too_small() {
+    let mut heap = Heap::<32>::new();
+    let words = Heap::<32>::bitmap_words(0x1000, 0x2000);
+    heap.set_bitmap(std::vec![0; words].leak(), 0x1000, 0x2000);
+}
+
+#[test]
+fn test_locked_heap_from_slice() {
+    static mut BUF: [u8; 1000] = [0; 1000];
+    let buf = unsafe { &mut *core::ptr::addr_of_mut!(BUF) };
+    let (begin, end) = (buf.as_ptr() as usize, buf.as_ptr() as usize + 1000);
+    let heap = LockedHeap::<32>::from_slice(buf);
+
+    // only the part of the buffer aligned to `usize` is used
+    #[cfg(feature = "stats")]
+    assert!(heap.total_bytes() <= 1000);
+    #[cfg(feature = "stats")]
+    assert!(heap.total_bytes() > 1000 - 2 * size_of::<usize>());
+    let layout = Layout::from_size_align(100, 1).unwrap();
+    let addr = heap.try_alloc(layout).unwrap().as_ptr() as usize;
+    assert!(begin <= addr && addr + 100 <= end);
+    assert!(heap.lock().contains(addr));
+}
+
+#[test]
+fn test_heap_verify() {
+    let word = size_of::<usize>();
+    let order = |size: usize| size.trailing_zeros() as usize;
+    let layout = Layout::from_size_align(64 * word, 64 * word).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    let begin = space as usize;
+    let new_heap = || {
+        let mut heap = Heap::<32>::new();
+        unsafe {
+            heap.add_to_heap(begin, begin + 64 * word);
+        }
+        heap
+    };
+
+    let mut heap = new_heap();
+    assert_eq!(heap.verify(), Ok(()));
+    let small = Layout::from_size_align(word, 1).unwrap();
+    let medium = Layout::from_size_align(5 * word, 1).unwrap();
+    let a = heap.alloc(small).unwrap();
+    let b = heap.alloc(medium).unwrap();
+    assert_eq!(heap.verify(), Ok(()));
+    heap.dealloc(a, small);
+    heap.dealloc(b, medium);
+    assert_eq!(heap.verify(), Ok(()));
+
+    // a block that is freed twice, without the merge that a real deallocation would do
+    unsafe { heap.free_list[order(8 * word)].push(b.as_ptr() as *mut usize) };
+    assert_eq!(heap.verify(), Err("free blocks overlap"));
+
+    // a free block inside a larger free block
+    let mut heap = new_heap();
+    unsafe { heap.free_list[order(word)].push((begin + 8 * word) as *mut usize) };
+    assert_eq!(heap.verify(), Err("free blocks overlap"));
+
+    let mut heap = new_heap();
+    let a = heap.alloc(small).unwrap();
+    // the second word of the free block at `a + 2 * word`
+    unsafe { heap.free_list[order(2 * word)].push(a.as_ptr().add(3 * word) as *mut usize) };
+    assert_eq!(heap.verify(), Err("free block is not aligned to its size"));
+
+    let mut heap = new_heap();
+    unsafe { heap.free_list[order(word)].push((begin + 64 * word) as *mut usize) };
+    assert_eq!(
+        heap.verify(),
+        Err("free block is outside the regions added to the heap")
+    );
+
+    // memory which is neither free nor allocated, which is only told with the statistics
+    #[cfg(feature = "stats")]
+    {
+        let mut heap = new_heap();
+        let _ = heap.alloc(small).unwrap();
+        heap.counters.sub_allocated(heap.allocated_bytes());
+        assert_eq!(
+            heap.verify(),
+            Err("free blocks do not add up to the available bytes")
+        );
+    }
+
+    unsafe { std::alloc::dealloc(space, layout) };
+}
+
+#[test]
+fn test_frame_allocator_dealloc_checked() {
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(16, 32);
+    let a = frame.alloc(4).unwrap();
+    let b = frame.alloc(4).unwrap();
+
+    assert!(frame.dealloc_checked(a, 4));
+    assert!(!frame.dealloc_checked(a, 4));
+    assert_eq!(frame.allocated_frames(), 4);
+
+    // partly free, or not aligned to its size
+    assert!(!frame.dealloc_checked(a, 8));
+    assert!(!frame.dealloc_checked(b + 1, 2));
+    assert!(!frame.dealloc_checked(b, usize::MAX));
+
+    assert!(frame.dealloc_checked(b, 3));
+    assert!(!frame.dealloc_checked(b, 4));
+    assert!(!frame.dealloc_checked(16, 16));
+    assert_eq!(frame.allocated_frames(), 0);
+    assert_eq!(frame.alloc(16), Some(16));
+}
+
+#[test]
+fn test_magazine() {
+    use crate::Magazine;
+    use rand::{Rng, SeedableRng};
+
+    let heap = LockedHeap::<32>::from_slice(std::vec![0u8; 4 << 20].leak());
+    std::thread::scope(|scope| {
+        for i in 0..4 {
+            let heap = &heap;
+            scope.spawn(move || {
+                let mut magazine = Magazine::<8>::new();
+                let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(i);
+                let mut live = std::vec::Vec::new();
+                for _ in 0..4000 {
+                    if live.is_empty() || rng.gen_bool(0.55) {
+                        // mostly cached sizes, with the occasional one straight from the heap
+                        let size = if rng.gen_bool(0.95) {
+                            rng.gen_range(1..=256)
+                        } else {
+                            rng.gen_range(2049..=8192)
+                        };
+                        let layout = Layout::from_size_align(size, 8).unwrap();
+                        let ptr = magazine.alloc(heap, layout).unwrap();
+                        // the block is not shared with any other thread
+                        unsafe { ptr.as_ptr().write_bytes(i as u8, size) };
+                        live.push((ptr, layout));
+                    } else {
+                        let (ptr, layout) = live.swap_remove(rng.gen_range(0..live.len()));
+                        let block =
+                            unsafe { core::slice::from_raw_parts(ptr.as_ptr(), layout.size()) };
+                        assert!(block.iter().all(|&byte| byte == i as u8));
+                        magazine.dealloc(heap, ptr, layout);
+                    }
+                }
+                for (ptr, layout) in live {
+                    magazine.dealloc(heap, ptr, layout);
+                }
+                assert!(magazine.cached_blocks() > 0);
+                magazine.flush(heap);
+                assert_eq!(magazine.cached_blocks(), 0);
+            });
+        }
+    });
+    #[cfg(feature = "stats")]
+    assert_eq!(heap.allocated_bytes(), 0);
+    assert_eq!(heap.lock().verify(), Ok(()));
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_heap_grow_shrink() {
+    let word = size_of::<usize>();
+    let layout = |size: usize| Layout::from_size_align(size, 1).unwrap();
+    let space =
+        unsafe { std::alloc::alloc(Layout::from_size_align(64 * word, 64 * word).unwrap()) };
+    let mut heap = Heap::<32>::new();
+    unsafe { heap.add_to_heap(space as usize, space as usize + 64 * word) };
+    let fill = |ptr: NonNull<u8>, size: usize| {
+        for i in 0..size {
+            unsafe { *ptr.as_ptr().add(i) = i as u8 };
+        }
+    };
+    let check = |ptr: NonNull<u8>, size: usize| {
+        for i in 0..size {
+            assert_eq!(unsafe { *ptr.as_ptr().add(i) }, i as u8);
+        }
+    };
+
+    let ptr = heap.alloc(layout(3 * word)).unwrap();
+    fill(ptr, 3 * word);
+    unsafe {
+        // same class: the block stays in place
+        let (same, size) = heap.grow(ptr, layout(3 * word), layout(4 * word)).unwrap();
+        assert_eq!((same, size), (ptr, 4 * word));
+        assert_eq!(heap.stats_alloc_user(), 4 * word);
+        let (same, _) = heap
+            .shrink(ptr, layout(4 * word), layout(3 * word))
+            .unwrap();
+        assert_eq!(same, ptr);
+        assert_eq!(heap.stats_alloc_user(), 3 * word);
+        check(ptr, 3 * word);
+
+        // next class: the contents move to a larger block
+        let (grown, size) = heap.grow(ptr, layout(3 * word), layout(6 * word)).unwrap();
+        assert_ne!(grown, ptr);
+        assert_eq!(size, 8 * word);
+        check(grown, 3 * word);
+        assert_eq!(heap.stats_alloc_user(), 6 * word);
+        assert_eq!(heap.stats_alloc_actual(), 8 * word);
+
+        // and back into a smaller one
+        fill(grown, 6 * word);
+        let (shrunk, size) = heap
+            .shrink(grown, layout(6 * word), layout(2 * word))
+            .unwrap();
+        assert_ne!(shrunk, grown);
+        assert_eq!(size, 2 * word);
+        check(shrunk, 2 * word);
+        assert_eq!(heap.stats_alloc_user(), 2 * word);
+        assert_eq!(heap.stats_alloc_actual(), 2 * word);
+
+        // zeroed growth in place clears the tail but keeps the contents
+        let (same, _) = heap
+            .shrink(shrunk, layout(2 * word), layout(word + 1))
+            .unwrap();
+        core::ptr::write_bytes(same.as_ptr().add(word + 1), 0xff, word - 1);
+        let (same, size) = heap
+            .grow_zeroed(same, layout(word + 1), layout(2 * word))
+            .unwrap();
+        assert_eq!(same, shrunk);
+        check(same, word + 1);
+        assert!((word + 1..size).all(|i| *same.as_ptr().add(i) == 0));
+
+        // and when moving into a larger block which held data before
+        let dirty = heap.alloc(layout(16 * word)).unwrap();
+        core::ptr::write_bytes(dirty.as_ptr(), 0xff, 16 * word);
+        heap.dealloc(dirty, layout(16 * word));
+        let (grown, size) = heap
+            .grow_zeroed(same, layout(2 * word), layout(9 * word))
+            .unwrap();
+        assert_eq!(size, 16 * word);
+        check(grown, word);
+        assert!((2 * word..size).all(|i| *grown.as_ptr().add(i) == 0));
+        assert_eq!(heap.stats_alloc_user(), 9 * word);
+        assert_eq!(heap.stats_alloc_actual(), 16 * word);
+
+        // a failed move keeps the old allocation
+        assert_eq!(
+            heap.grow(grown, layout(9 * word), layout(64 * word)),
+            Err(AllocError::OutOfMemory {
+                requested: 64 * word,
+                largest_free: 32 * word
+            })
+        );
+        check(grown, word);
+        heap.dealloc(grown, layout(9 * word));
+    }
+    assert_eq!(heap.stats_alloc_actual(), 0);
+    assert_eq!(heap.verify(), Ok(()));
+}
+
+#[test]
+fn test_frame_allocator_split_accounting() {
+    use rand::{Rng, SeedableRng};
+
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(0, 4096);
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+    let mut live = std::vec::Vec::new();
+    for _ in 0..5000 {
+        if live.is_empty() || rng.gen_bool(0.55) {
+            // every size splits down from whatever block is free
+            let count = 1 << rng.gen_range(0..8);
+            if let Some(start) = frame.alloc(count) {
+                assert_eq!(start % count, 0);
+                live.push((start, count));
+            }
+        } else {
+            let (start, count) = live.swap_remove(rng.gen_range(0..live.len()));
+            frame.dealloc(start, count);
+        }
+        let allocated: usize = live.iter().map(|&(_, count)| count).sum();
+        assert_eq!(frame.allocated_frames(), allocated);
+        assert_eq!(frame.available_frames(), 4096 - allocated);
+        let free: usize = frame.free_ranges().map(|range| range.len()).sum();
+        assert_eq!(free, 4096 - allocated);
+    }
+    for (start, count) in live {
+        frame.dealloc(start, count);
+    }
+    assert_eq!(
+        frame.free_ranges().collect::<std::vec::Vec<_>>(),
+        std::vec![0..4096]
+    );
+}
+
+#[test]
+fn test_heap_outstanding_allocations() {
+    let mut heap = Heap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    unsafe { heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize) };
+
+    let layout = Layout::from_size_align(size_of::<usize>(), 1).unwrap();
+    let a = heap.alloc(layout).unwrap();
+    let _b = heap.alloc(layout).unwrap();
+    let _c = heap.alloc(Layout::from_size_align(3, 1).unwrap()).unwrap();
+    let _zero = heap.alloc(Layout::from_size_align(0, 1).unwrap()).unwrap();
+    assert_eq!(heap.outstanding_allocations(), 3);
+    heap.dealloc(a, layout);
+    assert_eq!(heap.outstanding_allocations(), 2);
+    assert!(std::format!("{:?}", heap).contains("outstanding: 2"));
+}
+
+#[test]
+fn test_frame_allocator_dealloc_range() {
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(0, 16);
+    let mut frames = [0; 16];
+    assert_eq!(frame.alloc_many(16, &mut frames), 16);
+    frames.sort();
+    assert_eq!(frames, core::array::from_fn::<usize, 16, _>(|i| i));
+    assert_eq!(frame.alloc(1), None);
+
+    frame.dealloc_range(3..8);
+    assert_eq!(frame.allocated_frames(), 11);
+    let mut freed = [0; 5];
+    assert_eq!(frame.alloc_many(5, &mut freed), 5);
+    freed.sort();
+    assert_eq!(freed, [3, 4, 5, 6, 7]);
+    assert_eq!(frame.alloc(1), None);
+
+    // the blocks of the range merge with their free buddies
+    frame.dealloc_range(0..16);
+    assert_eq!(frame.allocated_frames(), 0);
+    assert_eq!(frame.alloc(16), Some(0));
+}
+
+#[test]
+#[cfg(feature = "paranoid")]
+#[should_panic(expected = "are not all allocated")]
+fn test_frame_allocator_dealloc_range_free() {
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(0, 16);
+    let start = frame.alloc(4).unwrap();
+    frame.dealloc_range(start..start + 5);
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_heap_guard() {
+    use crate::HeapGuard;
+
+    let space: [usize; 100] = [0; 100];
+    let mut heap = Heap::<32>::new();
+    unsafe { heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize) };
+    let available = heap.available_bytes();
+    let word = size_of::<usize>();
+    let guard = HeapGuard::new(heap);
+
+    let layout = Layout::from_size_align(3 * word, 1).unwrap();
+    let outer = guard.allocate(layout).unwrap();
+    {
+        let mut inner = guard.allocate(layout).unwrap();
+        assert!(inner.iter().all(|&byte| byte == 0));
+        inner.fill(0xff);
+        assert_eq!(inner.len(), layout.size());
+        assert_eq!(guard.heap().available_bytes(), available - 8 * word);
+    }
+    assert_eq!(guard.heap().available_bytes(), available - 4 * word);
+    assert_eq!(outer.layout(), layout);
+    drop(outer);
+
+    let heap = guard.into_inner();
+    assert_eq!(heap.available_bytes(), available);
+    assert_eq!(heap.outstanding_allocations(), 0);
+}
+
+#[test]
+fn test_util() {
+    use crate::util::{class_of, prev_power_of_two};
+
+    assert_eq!(prev_power_of_two(0), 0);
+    assert_eq!(prev_power_of_two(1), 1);
+    assert_eq!(prev_power_of_two(4096), 4096);
+    assert_eq!(prev_power_of_two(4097), 4096);
+    assert_eq!(prev_power_of_two(usize::MAX), 1 << (usize::BITS - 1));
+
+    let word = size_of::<usize>();
+    let word_order = word.trailing_zeros() as usize;
+    assert_eq!(class_of(0, 1), word_order);
+    assert_eq!(class_of(1, 1), word_order);
+    assert_eq!(class_of(4096, 1), 12);
+    assert_eq!(class_of(4097, 1), 13);
+    assert_eq!(class_of(8, 4096), 12);
+    assert_eq!(
+        class_of(1 << (usize::BITS - 1), 1),
+        usize::BITS as usize - 1
+    );
+    assert_eq!(class_of(usize::MAX, 1), usize::BITS as usize);
+
+    // the prediction matches the blocks of the heap, which do not depend on the alignment
+    let mut heap = Heap::<32>::new();
+    let space: [usize; 128] = [0; 128];
+    unsafe { heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(128) as usize) };
+    for size in 1..=32 * word {
+        let layout = Layout::from_size_align(size, word).unwrap();
+        let (ptr, block) = heap.alloc_with_size(layout).unwrap();
+        assert_eq!(block, 1 << class_of(size, 1));
+        heap.dealloc(ptr, layout);
+    }
+}
+
+#[test]
+fn test_heap_try_add_to_heap() {
+    let mut heap = Heap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    let begin = space.as_ptr() as usize;
+    let word = size_of::<usize>();
+    unsafe {
+        assert_eq!(
+            heap.try_add_to_heap(begin + 64, begin),
+            Err("region starts after its end")
+        );
+        // nothing fits between two unaligned addresses of the same word
+        assert_eq!(heap.try_add_to_heap(begin + 1, begin + 2), Ok(0));
+        #[cfg(feature = "stats")]
+        assert_eq!(heap.stats_total_bytes(), 0);
+
+        assert_eq!(
+            heap.try_add_to_heap(begin, begin + 64 * word),
+            Ok(64 * word)
+        );
+        #[cfg(feature = "paranoid")]
+        assert_eq!(
+            heap.try_add_to_heap(begin + 8 * word, begin + 80 * word),
+            Err("region overlaps memory already added to the heap")
+        );
+        assert_eq!(
+            heap.try_add_to_heap(begin + 64 * word, begin + 100 * word),
+            Ok(36 * word)
+        );
+    }
+    #[cfg(feature = "stats")]
+    assert_eq!(heap.stats_total_bytes(), 100 * word);
+    assert_eq!(heap.verify(), Ok(()));
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_locked_heap_stress() {
+    use core::cmp::min;
+    use rand::{Rng, SeedableRng};
+
+    let heap = LockedHeap::<32>::from_slice(std::vec![0u8; 8 << 20].leak());
+    let total = heap.total_bytes();
+    std::thread::scope(|scope| {
+        for i in 0..8 {
+            let heap = &heap;
+            scope.spawn(move || {
+                let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(i);
+                let mut live = std::vec::Vec::new();
+                for _ in 0..5000 {
+                    match rng.gen_range(0..10) {
+                        0..=4 => {
+                            let layout =
+                                Layout::from_size_align(rng.gen_range(1..=2048), 8).unwrap();
+                            let ptr = unsafe { heap.alloc(layout) };
+                            assert!(!ptr.is_null());
+                            unsafe { ptr.write_bytes(i as u8, layout.size()) };
+                            live.push((ptr, layout));
+                        }
+                        5 if !live.is_empty() => {
+                            let index = rng.gen_range(0..live.len());
+                            let (ptr, layout) = live[index];
+                            let new_size = rng.gen_range(1..=2048);
+                            let new_ptr = unsafe { heap.realloc(ptr, layout, new_size) };
+                            assert!(!new_ptr.is_null());
+                            let len = min(layout.size(), new_size);
+                            let block = unsafe { core::slice::from_raw_parts(new_ptr, len) };
+                            assert!(block.iter().all(|&byte| byte == i as u8));
+                            unsafe { new_ptr.write_bytes(i as u8, new_size) };
+                            live[index] = (new_ptr, Layout::from_size_align(new_size, 8).unwrap());
+                        }
+                        _ if !live.is_empty() => {
+                            let (ptr, layout) = live.swap_remove(rng.gen_range(0..live.len()));
+                            // no other thread wrote into the block
+                            let block = unsafe { core::slice::from_raw_parts(ptr, layout.size()) };
+                            assert!(block.iter().all(|&byte| byte == i as u8));
+                            unsafe { heap.dealloc(ptr, layout) };
+                        }
+                        _ => {}
+                    }
+                }
+                for (ptr, layout) in live {
+                    unsafe { heap.dealloc(ptr, layout) };
+                }
+            });
+        }
+    });
+
+    let heap = heap.lock();
+    assert_eq!(heap.outstanding_allocations(), 0);
+    assert_eq!(heap.stats_alloc_actual(), 0);
+    assert_eq!(heap.stats_alloc_user(), 0);
+    assert_eq!(heap.stats_total_bytes(), total);
+    assert_eq!(heap.verify(), Ok(()));
+}
+
+#[test]
+fn test_frame_allocator_alloc_low() {
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(0, 8);
+    frame.add_frame(16, 17);
+    frame.add_frame(32, 34);
+    let mut low = frame.clone();
+
+    // the smallest block that fits is above the larger free blocks
+    assert_eq!(frame.alloc(1), Some(16));
+    assert_eq!(low.alloc_low(1), Some(0));
+    assert_eq!(frame.alloc(2), Some(32));
+    assert_eq!(low.alloc_low(2), Some(2));
+    assert_eq!(low.alloc_low(1), Some(1));
+    assert_eq!(low.alloc_low(4), Some(4));
+    assert_eq!(low.alloc_low(1), Some(16));
+    assert_eq!(low.alloc_low(4), None);
+    assert_eq!(low.allocated_frames(), 9);
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_heap_large_region() {
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(256 * word, 256 * word).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    let begin = space as usize;
+    // blocks of up to 16 words, and two large regions of 48 and 128 words
+    let mut heap = Heap::<{ 5 + size_of::<usize>().trailing_zeros() as usize }>::new();
+    let cap = 16 * word;
+    unsafe {
+        heap.add_to_heap(begin, begin + 64 * word);
+        assert_eq!(
+            heap.add_large_region(begin + 128 * word, begin + 256 * word),
+            Ok(())
+        );
+        assert_eq!(
+            heap.add_large_region(begin + 64 * word, begin + 112 * word),
+            Ok(())
+        );
+        assert!(heap
+            .add_large_region(begin + 120 * word, begin + 120 * word)
+            .is_err());
+    }
+    assert_eq!(heap.stats_total_bytes(), 240 * word);
+
+    // the buddy blocks never use the large regions
+    let small = Layout::from_size_align(cap, 1).unwrap();
+    let blocks: std::vec::Vec<_> = (0..4).map(|_| heap.alloc(small).unwrap()).collect();
+    assert!(matches!(
+        heap.alloc(small),
+        Err(AllocError::OutOfMemory { .. })
+    ));
+
+    // the smallest large region that fits is used
+    let big = Layout::from_size_align(cap + 1, 1).unwrap();
+    let (a, size) = heap.alloc_with_size(big).unwrap();
+    assert_eq!((a.as_ptr() as usize, size), (begin + 64 * word, 48 * word));
+    let huge = Layout::from_size_align(100 * word, word).unwrap();
+    let b = heap.alloc(huge).unwrap();
+    assert_eq!(b.as_ptr() as usize, begin + 128 * word);
+    assert_eq!(
+        heap.alloc(big),
+        Err(AllocError::OutOfMemory {
+            requested: cap + 1,
+            largest_free: 0
+        })
+    );
+    assert_eq!(
+        heap.alloc(Layout::from_size_align(129 * word, 1).unwrap()),
+        Err(AllocError::SizeTooLarge {
+            requested: 129 * word,
+            max: 128 * word
+        })
+    );
+    assert_eq!(heap.stats_alloc_actual(), 240 * word);
+    assert_eq!(heap.outstanding_allocations(), 6);
+
+    heap.dealloc(b, huge);
+    let c = heap.alloc(big).unwrap();
+    assert_eq!(c, b);
+    heap.dealloc(a, big);
+    heap.dealloc(c, big);
+    for block in blocks {
+        heap.dealloc(block, small);
+    }
+    assert_eq!(heap.stats_alloc_actual(), 0);
+    assert_eq!(heap.stats_alloc_user(), 0);
+    assert_eq!(heap.verify(), Ok(()));
+    unsafe { std::alloc::dealloc(space, layout) };
+}
+
+#[test]
+fn test_heap_large_region_tracked() {
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(256 * word, 256 * word).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    let begin = space as usize;
+    // blocks of up to 16 words, and a large region of 128 words
+    let mut heap = Heap::<{ 5 + size_of::<usize>().trailing_zeros() as usize }>::new();
+    unsafe {
+        heap.add_to_heap(begin, begin + 64 * word);
+        assert_eq!(
+            heap.add_large_region(begin + 128 * word, begin + 256 * word),
+            Ok(())
+        );
+        #[cfg(feature = "paranoid")]
+        {
+            assert_eq!(
+                heap.add_large_region(begin + 32 * word, begin + 96 * word),
+                Err("region overlaps memory already added to the heap")
+            );
+            assert_eq!(
+                heap.try_add_to_heap(begin + 192 * word, begin + 224 * word),
+                Err("region overlaps memory already added to the heap")
+            );
+        }
+    }
+    assert!(heap.contains(begin + 128 * word));
+    assert!(heap.contains(begin + 256 * word - 1));
+    assert!(!heap.contains(begin + 64 * word));
+
+    // a large allocation is recorded like any other
+    let big = Layout::from_size_align(100 * word, word).unwrap();
+    let leak = heap.alloc(big).unwrap();
+    assert_eq!(leak.as_ptr() as usize, begin + 128 * word);
+    #[cfg(feature = "trace")]
+    assert_eq!(
+        heap.leaks().collect::<std::vec::Vec<_>>(),
+        [(begin + 128 * word, 100 * word)]
+    );
+    heap.dealloc(leak, big);
+    #[cfg(feature = "trace")]
+    assert_eq!(heap.leaks().count(), 0);
+    assert_eq!(heap.verify(), Ok(()));
+    unsafe { std::alloc::dealloc(space, layout) };
+}
+
+#[test]
+fn test_frame_allocator_fmt() {
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(0, 7);
+    frame.alloc(2).unwrap();
+    assert_eq!(
+        std::format!("{:?}", frame),
+        "FrameAllocator { allocated: 2, total: 7, available: 5, free_blocks: {0: 1, 2: 1} }"
+    );
+    assert_eq!(
+        std::format!("{}", frame),
+        "allocated 2 frames, total 7 frames, available 5 frames"
+    );
+}
+
+#[test]
+fn test_heap_alloc_exact_class() {
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(64 * word, 64 * word).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    let mut heap = Heap::<32>::new();
+    unsafe { heap.add_to_heap(space as usize, space as usize + 64 * word) };
+
+    let small = Layout::from_size_align(word, 1).unwrap();
+    let a = heap.alloc(small).unwrap();
+    let b = heap.alloc(small).unwrap();
+    assert_eq!(b.as_ptr() as usize, a.as_ptr() as usize + word);
+
+    // a free block of the exact class is reused without splitting
+    heap.dealloc(a, small);
+    let splits = heap.split_count();
+    assert_eq!(heap.alloc(small), Ok(a));
+    assert_eq!(heap.split_count(), splits);
+
+    // unless it is not aligned enough for the layout
+    heap.dealloc(b, small);
+    let aligned = Layout::from_size_align(word, 16 * word).unwrap();
+    let c = heap.alloc(aligned).unwrap();
+    assert_eq!(c.as_ptr() as usize % (16 * word), 0);
+    assert_eq!(heap.split_count(), splits + 4);
+    // splitting for `c` freed its buddy last
+    let d = heap.alloc(small).unwrap();
+    assert_eq!(d.as_ptr() as usize, c.as_ptr() as usize + word);
+    assert_eq!(heap.split_count(), splits + 4);
+
+    heap.dealloc(a, small);
+    heap.dealloc(d, small);
+    heap.dealloc(c, aligned);
+    assert_eq!(heap.verify(), Ok(()));
+    assert_eq!(heap.max_contiguous_alloc(), 64 * word);
+    unsafe { std::alloc::dealloc(space, layout) };
+}
+
+#[test]
+fn test_frame_allocator_from_ranges() {
+    let mut frame = FrameAllocator::<32>::from_ranges([0..3, 8..16, 100..101]);
+    assert_eq!(frame.total_frames(), 12);
+    assert_eq!(frame.alloc(8), Some(8));
+    assert_eq!(frame.alloc(2), Some(0));
+    assert_eq!(frame.alloc(1), Some(2));
+    assert_eq!(frame.alloc(1), Some(100));
+    assert_eq!(frame.alloc(1), None);
+    assert_eq!(frame.available_frames(), 0);
+}
+
+#[test]
+fn test_frame_allocator_max_order_merge() {
+    const BITS: usize = usize::BITS as usize;
+    let mut frame = FrameAllocator::<BITS>::new();
+    frame.add_frame(0, usize::MAX);
+    assert_eq!(frame.total_frames(), usize::MAX);
+
+    // splitting the top block all the way down and merging it back
+    assert_eq!(frame.alloc_low(1), Some(0));
+    frame.dealloc(0, 1);
+    assert_eq!(frame.alloc(1 << (BITS - 1)), Some(0));
+    frame.dealloc(0, 1 << (BITS - 1));
+
+    // the block just below the top order merges with its buddy into the top order
+    assert_eq!(frame.alloc_low(1 << (BITS - 2)), Some(0));
+    assert_eq!(frame.alloc_low(1 << (BITS - 2)), Some(1 << (BITS - 2)));
+    frame.dealloc(1 << (BITS - 2), 1 << (BITS - 2));
+    frame.dealloc(0, 1 << (BITS - 2));
+    assert_eq!(frame.alloc(1 << (BITS - 1)), Some(0));
+    assert_eq!(frame.allocated_frames(), 1 << (BITS - 1));
+}
+
+#[test]
+fn test_locked_heap_try_lock_alloc() {
+    let heap = LockedHeap::<32>::from_slice(std::vec![0u8; 4096].leak());
+    let layout = Layout::from_size_align(16, 8).unwrap();
+
+    let guard = heap.lock();
+    std::thread::scope(|scope| {
+        // the other thread gives up instead of waiting for the lock
+        let locked = scope.spawn(|| heap.try_lock_alloc(layout).is_none());
+        assert!(locked.join().unwrap());
+    });
+    drop(guard);
+
+    let ptr = heap.try_lock_alloc(layout).unwrap().unwrap();
+    #[cfg(feature = "stats")]
+    assert_eq!(heap.allocated_bytes(), 16);
+    heap.dealloc_ptr(ptr, layout);
+}
+
+#[test]
+fn test_heap_can_alloc() {
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(16 * word, 16 * word).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) };
+    let mut heap = Heap::<32>::new();
+    unsafe { heap.add_to_heap(space as usize, space as usize + 16 * word) };
+    let size = |words: usize| Layout::from_size_align(words * word, 1).unwrap();
+
+    // by splitting the single block, then from a block of the exact class
+    assert!(heap.can_alloc(size(1)));
+    assert!(heap.can_alloc(size(16)));
+    assert!(!heap.can_alloc(size(17)));
+    assert!(heap.can_alloc(Layout::from_size_align(0, 1).unwrap()));
+    let a = heap.alloc(size(1)).unwrap();
+    assert!(heap.can_alloc(size(1)));
+    assert!(heap.can_alloc(size(8)));
+    assert!(!heap.can_alloc(size(16)));
+
+    heap.dealloc(a, size(1));
+
+    // fragmented: half of the words are free, but no two of them are contiguous
+    let words: std::vec::Vec<_> = (0..16).map(|_| heap.alloc(size(1)).unwrap()).collect();
+    for ptr in words.iter().step_by(2) {
+        heap.dealloc(*ptr, size(1));
+    }
+    #[cfg(feature = "stats")]
+    assert_eq!(heap.available_bytes(), 8 * word);
+    assert!(heap.can_alloc(size(1)));
+    assert!(!heap.can_alloc(size(2)));
+    // a free block of the size, but not one aligned enough
+    assert!(!heap.can_alloc(Layout::from_size_align(word, 2 * word).unwrap()));
+
+    // every answer matches the allocation as the blocks are freed
+    for ptr in words.iter().skip(1).step_by(2) {
+        heap.dealloc(*ptr, size(1));
+        for words in 1..=17 {
+            let layout = size(words);
+            let expected = heap.can_alloc(layout);
+            let result = heap.alloc(layout);
+            assert_eq!(result.is_ok(), expected);
+            if let Ok(ptr) = result {
+                heap.dealloc(ptr, layout);
+            }
+        }
+    }
+    assert!(heap.can_alloc(size(16)));
+    unsafe { std::alloc::dealloc(space, layout) };
+}
+
+#[test]
+fn test_frame_allocator_zero_count() {
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(0, 4);
+    assert_eq!(frame.alloc(0), None);
+    assert_eq!(frame.alloc_low(0), None);
+    assert_eq!(frame.alloc_handle(0), None);
+    assert_eq!(frame.alloc_contiguous(0), None);
+    assert_eq!(frame.allocated_frames(), 0);
+
+    let start = frame.alloc(1).unwrap();
+    frame.dealloc(start, 0);
+    assert!(!frame.dealloc_checked(start, 0));
+    assert_eq!(frame.allocated_frames(), 1);
+    assert!(frame.is_allocated(start));
+    frame.dealloc(start, 1);
+    assert_eq!(frame.allocated_frames(), 0);
+}
+
+#[test]
+fn test_frame_allocator_reserve() {
+    use std::vec::Vec;
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(0, 64);
+    assert!(frame.reserve(10..20));
+    assert_eq!(frame.total_frames(), 54);
+    assert!((10..20).all(|i| !frame.is_free(i)));
+    assert!((0..10).chain(20..64).all(|i| frame.is_free(i)));
+
+    // reserved or missing frames can not be reserved again, and nothing changes then
+    assert!(!frame.reserve(15..25));
+    assert!(!frame.reserve(60..70));
+    assert!(frame.is_free(20) && frame.is_free(60));
+    assert_eq!(frame.total_frames(), 54);
+
+    let mut allocated = Vec::new();
+    while let Some(start) = frame.alloc(1) {
+        allocated.push(start);
+    }
+    allocated.sort();
+    let expected: Vec<usize> = (0..10).chain(20..64).collect();
+    assert_eq!(allocated, expected);
+    assert!(!frame.reserve(30..31));
+
+    for start in allocated {
+        frame.dealloc(start, 1);
+    }
+    assert_eq!(frame.available_frames(), 54);
+    assert!(frame.reserve(0..10));
+    assert!(frame.reserve(20..64));
+    assert!(frame.reserve(0..0));
+    assert_eq!(frame.total_frames(), 0);
+    assert_eq!(frame.free_ranges().count(), 0);
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_heap_reserve() {
+    use std::vec::Vec;
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(64 * word, 64 * word).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) } as usize;
+    let mut heap = Heap::<32>::new();
+    unsafe { heap.add_to_heap(space, space + 64 * word) };
+
+    assert!(unsafe { heap.reserve(space + 10 * word, space + 20 * word) });
+    // rounded outwards to the whole word
+    assert!(unsafe { heap.reserve(space + 5 * word + 1, space + 6 * word - 1) });
+    assert_eq!(heap.total_bytes(), 53 * word);
+    assert!(heap.verify().is_ok());
+    assert!(!heap.contains(space + 15 * word));
+    assert!(heap.contains(space + 20 * word));
+
+    // reserved memory can not be reserved again, and nothing changes then
+    assert!(!unsafe { heap.reserve(space + 15 * word, space + 25 * word) });
+    assert!(!unsafe { heap.reserve(space + 60 * word, space + 70 * word) });
+    assert_eq!(heap.total_bytes(), 53 * word);
+    assert!(heap.verify().is_ok());
+
+    let one = Layout::from_size_align(word, 1).unwrap();
+    let mut allocated = Vec::new();
+    while let Ok(ptr) = heap.alloc(one) {
+        allocated.push((ptr.as_ptr() as usize - space) / word);
+    }
+    allocated.sort();
+    let expected: Vec<usize> = (0..5).chain(6..10).chain(20..64).collect();
+    assert_eq!(allocated, expected);
+    assert!(!unsafe { heap.reserve(space + 30 * word, space + 31 * word) });
+
+    for index in allocated {
+        heap.dealloc(
+            NonNull::new((space + index * word) as *mut u8).unwrap(),
+            one,
+        );
+    }
+    assert_eq!(heap.available_bytes(), 53 * word);
+    assert!(heap.verify().is_ok());
+    // the memory in between can not merge across the reserved ranges
+    assert_eq!(heap.max_contiguous_alloc(), 32 * word);
+
+    unsafe { std::alloc::dealloc(space as *mut u8, layout) };
+}
+
+#[test]
+fn test_linked_list_peek() {
+    let mut values = [0usize; 2];
+    let mut list = linked_list::LinkedList::new();
+    assert_eq!(list.peek(), None);
+
+    unsafe {
+        list.push(&mut values[0] as *mut usize);
+        list.push(&mut values[1] as *mut usize);
+    }
+    let head = list.peek();
+    assert_eq!(head, Some(&mut values[1] as *mut usize));
+    assert_eq!(list.len(), 2);
+    assert_eq!(list.pop(), head);
+    assert_eq!(list.peek(), Some(&mut values[0] as *mut usize));
+    list.pop();
+    assert_eq!(list.peek(), None);
+}
+
+#[cfg(feature = "use_std_mutex")]
+#[test]
+fn test_std_mutex() {
+    use crate::StdRawMutex;
+    use lock_api::RawMutex;
+    use std::sync::Arc;
+    use std::thread;
+
+    let lock = StdRawMutex::INIT;
+    lock.lock();
+    assert!(lock.is_locked());
+    assert!(!lock.try_lock());
+    unsafe { lock.unlock() };
+    assert!(lock.try_lock());
+    unsafe { lock.unlock() };
+
+    // the default lock of the locked types is the blocking one
+    let heap: Arc<LockedHeap<32, StdRawMutex>> = Arc::new(LockedHeap::<32>::new());
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(1024 * word, word).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) } as usize;
+    unsafe { heap.lock().add_to_heap(space, space + 1024 * word) };
+
+    // waiting threads sleep until the heap is unlocked
+    let guard = heap.lock();
+    let threads: std::vec::Vec<_> = (0..4)
+        .map(|_| {
+            let heap = heap.clone();
+            thread::spawn(move || {
+                let small = Layout::from_size_align(word, 1).unwrap();
+                for _ in 0..100 {
+                    let ptr = heap.try_alloc(small).unwrap();
+                    heap.dealloc_ptr(ptr, small);
+                }
+            })
+        })
+        .collect();
+    drop(guard);
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(heap.allocated_bytes(), 0);
+
+    let frame = LockedFrameAllocator::<32>::new();
+    frame.add_frame(0, 4);
+    let guard = frame.lock();
+    assert!(frame.try_lock().is_none());
+    drop(guard);
+    assert_eq!(frame.alloc(4), Some(0));
+
+    unsafe { std::alloc::dealloc(space as *mut u8, layout) };
+}
+
+#[test]
+fn test_frame_allocator_alloc_near() {
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(0, 16);
+    // a larger block is split towards the hint
+    assert_eq!(frame.alloc_near(1, 13), Some(13));
+    // the smallest size class that fits comes first, even if it is far from the hint
+    assert_eq!(frame.alloc_near(4, 5), Some(8));
+    assert_eq!(frame.alloc_near(1, 100), Some(12));
+    assert_eq!(frame.alloc_near(4, 6), Some(4));
+    assert_eq!(frame.allocated_frames(), 10);
+    frame.dealloc(13, 1);
+    frame.dealloc(8, 4);
+    frame.dealloc(12, 1);
+    frame.dealloc(4, 4);
+    assert_eq!(frame.free_ranges().next(), Some(0..16));
+    assert_eq!(frame.free_ranges().count(), 1);
+
+    // fragmented: only a few single frames are free
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(0, 64);
+    for _ in 0..64 {
+        let _ = frame.alloc(1).unwrap();
+    }
+    for free in [3, 20, 40] {
+        frame.dealloc(free, 1);
+    }
+    assert_eq!(frame.alloc_near(1, 22), Some(20));
+    assert_eq!(frame.alloc_near(1, 38), Some(40));
+    assert_eq!(frame.alloc_near(1, 30), Some(3));
+    assert_eq!(frame.alloc_near(1, 30), None);
+    assert_eq!(frame.alloc_near(0, 30), None);
+}
+
+#[cfg(not(feature = "stats"))]
+#[test]
+fn test_heap_without_stats() {
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(64 * word, 64 * word).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) } as usize;
+    let mut heap = Heap::<32>::new();
+    unsafe { heap.add_to_heap(space, space + 64 * word) };
+
+    let small = Layout::from_size_align(3 * word, 1).unwrap();
+    let a = heap.alloc(small).unwrap();
+    let b = heap.alloc(small).unwrap();
+    assert_ne!(a, b);
+    assert_eq!(heap.outstanding_allocations(), 2);
+    // no byte is counted
+    assert!(!format!("{:?}", heap).contains("total"));
+    assert_eq!(
+        format!("{}", heap),
+        format!(
+            "2 outstanding allocations, largest free block {} B",
+            32 * word
+        )
+    );
+
+    heap.dealloc(a, small);
+    heap.dealloc(b, small);
+    assert!(heap.verify().is_ok());
+    assert_eq!(heap.max_contiguous_alloc(), 64 * word);
+
+    unsafe { std::alloc::dealloc(space as *mut u8, layout) };
+}
+
+#[test]
+fn test_heap_merge_all() {
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(64 * word, 64 * word).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) } as usize;
+    let mut heap = Heap::<32>::new();
+    // adjacent regions added one by one are not merged
+    for i in 0..8 {
+        unsafe { heap.add_to_heap(space + i * 8 * word, space + (i + 1) * 8 * word) };
+    }
+    assert_eq!(heap.max_contiguous_alloc(), 8 * word);
+
+    heap.merge_all();
+    assert_eq!(heap.max_contiguous_alloc(), 64 * word);
+    assert_eq!(heap.free_list_len((64 * word).trailing_zeros() as usize), 1);
+    assert_eq!(heap.merge_count(), 7);
+    assert!(heap.verify().is_ok());
+
+    // a block in use keeps its buddies apart
+    let mut heap = Heap::<32>::new();
+    unsafe {
+        heap.add_to_heap(space, space + 32 * word);
+        heap.add_to_heap(space + 32 * word, space + 64 * word);
+    }
+    let small = Layout::from_size_align(word, 1).unwrap();
+    let ptr = heap.alloc(small).unwrap();
+    heap.merge_all();
+    assert_eq!(heap.max_contiguous_alloc(), 32 * word);
+    assert_eq!(heap.merge_count(), 0);
+    heap.dealloc(ptr, small);
+    assert!(heap.verify().is_ok());
+
+    unsafe { std::alloc::dealloc(space as *mut u8, layout) };
+}
+
+#[test]
+fn test_heap_alloc_alignment() {
+    use rand::{Rng, SeedableRng};
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(1 << 17, word).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) } as usize;
+    let mut heap = Heap::<32>::new();
+    // the blocks are aligned to their address, not to the start of the region
+    unsafe { heap.add_to_heap(space + 3 * word, space + (1 << 17) - 5 * word) };
+
+    // every combination, in a heap that is fragmented by the allocations of the previous ones
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+    let mut live = std::vec::Vec::new();
+    for size in (0..=12).flat_map(|shift| [(1 << shift) - 1, 1 << shift, (1 << shift) + 1]) {
+        for align in (0..=12).map(|shift| 1 << shift) {
+            let layout = Layout::from_size_align(size, align).unwrap();
+            let ptr = heap.alloc(layout).unwrap();
+            assert!(
+                ptr.as_ptr() as usize & (align - 1) == 0,
+                "{:?} allocated at {:#x}",
+                layout,
+                ptr.as_ptr() as usize
+            );
+            if rng.gen_bool(0.5) {
+                live.push((ptr, layout));
+            } else {
+                heap.dealloc(ptr, layout);
+            }
+            if live.len() > 16 {
+                let (ptr, layout) = live.swap_remove(rng.gen_range(0..live.len()));
+                heap.dealloc(ptr, layout);
+            }
+        }
+    }
+    for (ptr, layout) in live {
+        heap.dealloc(ptr, layout);
+    }
+    #[cfg(feature = "stats")]
+    assert_eq!(heap.allocated_bytes(), 0);
+    assert!(heap.verify().is_ok());
+
+    unsafe { std::alloc::dealloc(space as *mut u8, layout) };
+}
+
+#[test]
+fn test_heap_dealloc_region_boundaries() {
+    use std::vec::Vec;
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(32 * word, 32 * word).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) } as *mut usize;
+    let canary = 0x5a5a_5a5a_usize;
+    for i in (0..3).chain(29..32) {
+        unsafe { *space.add(i) = canary };
+    }
+    let begin = space as usize;
+    let mut heap = Heap::<32>::new();
+    // not a power of two, so the first and last blocks have their buddies outside the region
+    unsafe { heap.add_to_heap(begin + 3 * word, begin + 29 * word) };
+    let initial: Vec<_> = heap.iter_free().collect();
+
+    let one = Layout::from_size_align(word, 1).unwrap();
+    let mut words: Vec<_> = (0..26).map(|_| heap.alloc(one).unwrap()).collect();
+    assert!(heap.alloc(one).is_err());
+    words.sort();
+    let first = words.remove(0);
+    let last = words.pop().unwrap();
+    assert_eq!(first.as_ptr() as usize, begin + 3 * word);
+    assert_eq!(last.as_ptr() as usize, begin + 28 * word);
+
+    // nothing to merge with below the start or above the end of the region
+    let merges = heap.merge_count();
+    heap.dealloc(first, one);
+    heap.dealloc(last, one);
+    assert_eq!(heap.merge_count(), merges);
+    assert_eq!(heap.free_list_len(word.trailing_zeros() as usize), 2);
+    assert!(heap.verify().is_ok());
+
+    for ptr in words {
+        heap.dealloc(ptr, one);
+    }
+    let mut free: Vec<_> = heap.iter_free().collect();
+    let mut expected = initial;
+    free.sort();
+    expected.sort();
+    assert_eq!(free, expected);
+    assert!(heap.verify().is_ok());
+    // the words around the region were never read as links or written
+    for i in (0..3).chain(29..32) {
+        assert_eq!(unsafe { *space.add(i) }, canary);
+    }
+
+    unsafe { std::alloc::dealloc(space as *mut u8, layout) };
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_heap_alloc_order() {
+    use std::vec::Vec;
+    let word = size_of::<usize>();
+    let word_order = word.trailing_zeros() as usize;
+    let layout = Layout::from_size_align(4096, 4096).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) } as usize;
+    let mut heap = Heap::<32>::new();
+    unsafe { heap.add_to_heap(space, space + 4096) };
+
+    let mut blocks = Vec::new();
+    for order in word_order..10 {
+        let before = heap.stats();
+        let ptr = heap.alloc_order(order).unwrap();
+        assert!(ptr.as_ptr() as usize & ((1 << order) - 1) == 0);
+        assert_eq!(heap.stats().allocated, before.allocated + (1 << order));
+        assert_eq!(heap.stats().user, before.user + (1 << order));
+        blocks.push((ptr, order));
+    }
+    // too small for a block, above the max order, or no block left of the order
+    assert_eq!(heap.alloc_order(word_order - 1), None);
+    assert_eq!(heap.alloc_order(32), None);
+    assert_eq!(heap.alloc_order(12), None);
+
+    for (ptr, order) in blocks.into_iter().rev() {
+        let before = heap.stats_alloc_actual();
+        heap.dealloc_order(ptr, order);
+        assert_eq!(heap.stats_alloc_actual(), before - (1 << order));
+    }
+    assert_eq!(
+        heap.stats(),
+        crate::HeapStats {
+            total: 4096,
+            ..Default::default()
+        }
+    );
+    assert_eq!(heap.max_contiguous_alloc(), 4096);
+
+    unsafe { std::alloc::dealloc(space as *mut u8, layout) };
+}
+
+#[test]
+fn test_heap_fuzz() {
+    use rand::{Rng, SeedableRng};
+    use std::vec::Vec;
+
+    #[derive(Clone, Copy, Debug)]
+    enum Op {
+        // allocate `size` words aligned to 2^`align` words
+        Alloc(usize, u32),
+        // deallocate the live allocation at this index, modulo their number
+        Dealloc(usize),
+    }
+
+    // a small max order, so that top-order blocks are split and merged again often
+    const ORDER: usize = 6 + size_of::<usize>().trailing_zeros() as usize;
+    let word = size_of::<usize>();
+    let top = 1 << (ORDER - 1);
+    let layout = Layout::from_size_align(8 * top, top).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) } as usize;
+
+    let run = |ops: &mut dyn Iterator<Item = Op>| {
+        let mut heap = Heap::<ORDER>::new();
+        // some top-order blocks, and smaller blocks at both ends
+        let (begin, end) = (space + 3 * word, space + 8 * top - 5 * word);
+        unsafe { heap.add_to_heap(begin, end) };
+        #[cfg(feature = "stats")]
+        let total = heap.total_bytes();
+        // shadow model of the outstanding allocations, and the byte each of them is filled with
+        let mut live: Vec<(NonNull<u8>, Layout, u8)> = Vec::new();
+        for (i, op) in ops.enumerate() {
+            match op {
+                Op::Alloc(size, align) => {
+                    let layout = Layout::from_size_align(size * word, word << align).unwrap();
+                    if let Ok(ptr) = heap.alloc(layout) {
+                        let addr = ptr.as_ptr() as usize;
+                        assert!(addr & (layout.align() - 1) == 0, "{:?}", op);
+                        if size > 0 {
+                            assert!(addr >= begin && addr + layout.size() <= end, "{:?}", op);
+                            for &(other, other_layout, _) in &live {
+                                let other = other.as_ptr() as usize;
+                                assert!(
+                                    addr + layout.size() <= other
+                                        || other + other_layout.size() <= addr,
+                                    "{:?} overlaps {:#x}",
+                                    op,
+                                    other
+                                );
+                            }
+                        }
+                        unsafe { core::ptr::write_bytes(ptr.as_ptr(), i as u8, layout.size()) };
+                        live.push((ptr, layout, i as u8));
+                    }
+                }
+                Op::Dealloc(index) if !live.is_empty() => {
+                    let (ptr, layout, fill) = live.swap_remove(index % live.len());
+                    let bytes = unsafe { core::slice::from_raw_parts(ptr.as_ptr(), layout.size()) };
+                    assert!(bytes.iter().all(|&byte| byte == fill), "{:?}", op);
+                    heap.dealloc(ptr, layout);
+                }
+                Op::Dealloc(_) => {}
+            }
+            assert_eq!(heap.verify(), Ok(()), "after {:?}", op);
+            let outstanding = live
+                .iter()
+                .filter(|(_, layout, _)| layout.size() > 0)
+                .count();
+            assert_eq!(heap.outstanding_allocations(), outstanding);
+        }
+        for (ptr, layout, _) in live {
+            heap.dealloc(ptr, layout);
+        }
+        assert_eq!(heap.verify(), Ok(()));
+        #[cfg(feature = "stats")]
+        assert_eq!(heap.available_bytes(), total);
+        assert_eq!(heap.free_list_len(ORDER - 1), 6);
+    };
+
+    // regression: free top-order blocks, which must stop merging at the top order instead of
+    // indexing past the free lists, and ask for an alignment above the max order
+    let corpus = [
+        Op::Alloc(top / word, 0),
+        Op::Alloc(top / word, 0),
+        Op::Alloc(1, 6),
+        Op::Dealloc(0),
+        Op::Dealloc(0),
+        Op::Dealloc(0),
+    ];
+    run(&mut corpus.iter().copied());
+
+    for seed in 0..8 {
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+        let mut ops = (0..2000).map(|_| {
+            if rng.gen_bool(0.55) {
+                let size = if rng.gen_bool(0.1) {
+                    rng.gen_range(0..=top / word)
+                } else {
+                    rng.gen_range(0..=8)
+                };
+                Op::Alloc(size, rng.gen_range(0..=ORDER as u32 - 4))
+            } else {
+                Op::Dealloc(rng.gen())
+            }
+        });
+        run(&mut ops);
+    }
+
+    unsafe { std::alloc::dealloc(space as *mut u8, layout) };
+}
+
+#[test]
+fn test_frame_allocator_free_frames() {
+    use rand::{Rng, SeedableRng};
+
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(3, 1000);
+    assert_eq!(frame.free_frames(), 997);
+    assert!(frame.reserve(100..200));
+    frame.add_frame(2000, 2100);
+    assert_eq!(frame.free_frames(), 997);
+
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+    let mut live = std::vec::Vec::new();
+    for _ in 0..2000 {
+        if live.is_empty() || rng.gen_bool(0.55) {
+            let count = rng.gen_range(1..=20);
+            let start = match rng.gen_range(0..3) {
+                0 => frame.alloc(count),
+                1 => frame.alloc_low(count),
+                _ => frame.alloc_near(count, rng.gen_range(0..2100)),
+            };
+            if let Some(start) = start {
+                live.push((start, count));
+            }
+        } else {
+            let (start, count) = live.swap_remove(rng.gen_range(0..live.len()));
+            frame.dealloc(start, count);
+        }
+        assert_eq!(frame.free_frames(), frame.available_frames());
+    }
+    for (start, count) in live {
+        frame.dealloc(start, count);
+    }
+    assert_eq!(frame.free_frames(), 997);
+    assert_eq!(frame.allocated_frames(), 0);
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_heap_size_histogram() {
+    let word = size_of::<usize>();
+    let word_order = word.trailing_zeros() as usize;
+    let layout = Layout::from_size_align(4096, 4096).unwrap();
+    let space = unsafe { std::alloc::alloc(layout) } as usize;
+    let mut heap = Heap::<32>::new();
+    unsafe { heap.add_to_heap(space, space + 4096) };
+
+    let size = |bytes: usize| Layout::from_size_align(bytes, 1).unwrap();
+    let small: std::vec::Vec<_> = (0..3).map(|_| heap.alloc(size(1)).unwrap()).collect();
+    let medium = heap.alloc(size(3 * word)).unwrap();
+    let large = heap.alloc(size(1000)).unwrap();
+    let _zero = heap.alloc(size(0)).unwrap();
+
+    let mut expected = [0; 32];
+    expected[word_order] = 3;
+    expected[word_order + 2] = 1;
+    expected[10] = 1;
+    assert_eq!(heap.size_histogram(), expected);
+    assert_eq!(
+        heap.size_histogram().iter().sum::<usize>(),
+        heap.outstanding_allocations()
+    );
+
+    for ptr in small {
+        heap.dealloc(ptr, size(1));
+    }
+    heap.dealloc(large, size(1000));
+    expected[word_order] = 0;
+    expected[10] = 0;
+    assert_eq!(heap.size_histogram(), expected);
+    heap.dealloc(medium, size(3 * word));
+    assert_eq!(heap.size_histogram(), [0; 32]);
+
+    unsafe { std::alloc::dealloc(space as *mut u8, layout) };
+}
+
+#[test]
+fn test_locked_heap_with() {
+    let heap = LockedHeap::<32>::from_slice(std::vec![0u8; 4096].leak());
+    let layout = Layout::from_size_align(16, 8).unwrap();
+
+    let (a, b, c) = heap.with(|heap| {
+        let a = heap.alloc(layout).unwrap();
+        let b = heap.alloc(layout).unwrap();
+        let c = heap.alloc(layout).unwrap();
+        assert_eq!(heap.outstanding_allocations(), 3);
+        (a, b, c)
+    });
+    assert!(a != b && b != c && a != c);
+    // the lock is released once the closure returns
+    assert!(!heap.is_locked());
+    #[cfg(feature = "stats")]
+    assert_eq!(heap.allocated_bytes(), 48);
+
+    heap.with(|heap| {
+        for ptr in [a, b, c] {
+            heap.dealloc(ptr, layout);
+        }
+    });
+    #[cfg(feature = "stats")]
+    assert_eq!(heap.allocated_bytes(), 0);
+}
+
+#[test]
+fn test_locked_heap_ref_global_alloc() {
+    static HEAP: LockedHeap<32> = LockedHeap::<32>::new();
+    unsafe {
+        HEAP.lock()
+            .init(std::vec![0u8; 4096].leak().as_mut_ptr() as usize, 4096)
+    };
+
+    // code generic over the allocator, given the heap by reference
+    fn roundtrip<A: GlobalAlloc>(allocator: A) -> usize {
+        let layout = Layout::from_size_align(16, 8).unwrap();
+        unsafe {
+            let ptr = allocator.alloc_zeroed(layout);
+            assert!(!ptr.is_null());
+            assert_eq!(*ptr, 0);
+            let ptr = allocator.realloc(ptr, layout, 100);
+            assert!(!ptr.is_null());
+            let addr = ptr as usize;
+            allocator.dealloc(ptr, Layout::from_size_align(100, 8).unwrap());
+            addr
+        }
+    }
+    let handle: &'static LockedHeap<32> = &HEAP;
+    let addr = roundtrip(handle);
+    assert!(HEAP.lock().contains(addr));
+    #[cfg(feature = "stats")]
+    assert_eq!(HEAP.allocated_bytes(), 0);
+    #[cfg(feature = "stats")]
+    assert_eq!(HEAP.stats().user, 0);
+}
+
+#[test]
+fn test_frame_allocator_frame_cache() {
+    use rand::{Rng, SeedableRng};
+    use std::collections::BTreeSet;
+
+    let mut frame = FrameAllocator::<32>::new();
+    frame.add_frame(0, 256);
+    frame.set_frame_cache(true);
+
+    // a freed frame is handed out again first, and is still free meanwhile
+    let a = frame.alloc(1).unwrap();
+    let b = frame.alloc(1).unwrap();
+    frame.dealloc(a, 1);
+    assert!(frame.is_free(a));
+    assert!(!frame.dealloc_checked(a, 1));
+    assert_eq!(frame.free_frames(), frame.available_frames());
+    assert_eq!(frame.alloc(1), Some(a));
+    frame.dealloc(a, 1);
+    frame.dealloc(b, 1);
+
+    // no frame is ever handed out twice, and all allocators agree on the free frames
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+    let mut live = BTreeSet::new();
+    let mut blocks = std::vec::Vec::new();
+    for _ in 0..5000 {
+        if rng.gen_bool(0.05) {
+            if let Some(start) = frame.alloc(8) {
+                assert!((start..start + 8).all(|frame| !live.contains(&frame)));
+                blocks.push(start);
+            }
+        } else if rng.gen_bool(0.05) && !blocks.is_empty() {
+            frame.dealloc(blocks.swap_remove(rng.gen_range(0..blocks.len())), 8);
+        } else if live.is_empty() || rng.gen_bool(0.5) {
+            if let Some(start) = frame.alloc(1) {
+                assert!(live.insert(start), "frame {} allocated twice", start);
+                assert!(blocks
+                    .iter()
+                    .all(|&block| !(block..block + 8).contains(&start)));
+            }
+        } else {
+            let start = *live.iter().nth(rng.gen_range(0..live.len())).unwrap();
+            live.remove(&start);
+            frame.dealloc(start, 1);
+        }
+        assert_eq!(frame.allocated_frames(), live.len() + 8 * blocks.len());
+        assert_eq!(frame.free_frames(), frame.available_frames());
+        let free: usize = frame.free_ranges().map(|range| range.len()).sum();
+        assert_eq!(free, frame.available_frames());
+    }
+    for start in live {
+        frame.dealloc(start, 1);
+    }
+    for start in blocks {
+        frame.dealloc(start, 8);
+    }
+
+    // disabling the cache merges everything back
+    frame.set_frame_cache(false);
+    assert_eq!(frame.free_ranges().next(), Some(0..256));
+    assert_eq!(frame.free_ranges().count(), 1);
+}
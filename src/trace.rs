@@ -0,0 +1,52 @@
+//! Provide the record of outstanding allocations of a heap, for the `trace` feature
+
+/// A fixed-size ring buffer of outstanding allocations, as `(ptr, size)` pairs
+///
+/// The buffer lives inside the heap, so recording an allocation never allocates. An allocation
+/// takes the next slot which is not used by an outstanding allocation. Only once every slot is
+/// used are outstanding allocations overwritten, starting with the oldest slots.
+#[derive(Clone, Copy)]
+pub(crate) struct Trace<const N: usize> {
+    entries: [Option<(usize, usize)>; N],
+    // slot after the one written by the last allocation, where the search for a free slot starts
+    next: usize,
+}
+
+impl<const N: usize> Trace<N> {
+    /// Create an empty trace
+    pub(crate) const fn new() -> Self {
+        Trace {
+            entries: [None; N],
+            next: 0,
+        }
+    }
+
+    /// Record an allocation of `size` bytes at `ptr`
+    pub(crate) fn record(&mut self, ptr: usize, size: usize) {
+        if N == 0 {
+            return;
+        }
+        let slot = (0..N)
+            .map(|i| (self.next + i) % N)
+            .find(|&slot| self.entries[slot].is_none())
+            .unwrap_or(self.next);
+        self.entries[slot] = Some((ptr, size));
+        self.next = (slot + 1) % N;
+    }
+
+    /// Forget the allocation at `ptr`, if it was recorded
+    pub(crate) fn remove(&mut self, ptr: usize) {
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|entry| matches!(entry, Some((p, _)) if *p == ptr))
+        {
+            *entry = None;
+        }
+    }
+
+    /// Return an iterator over the recorded allocations
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.entries.iter().flatten().copied()
+    }
+}
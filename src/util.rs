@@ -0,0 +1,33 @@
+//! Helpers to predict how the allocators round sizes, e.g. to lay out regions before adding them
+
+use core::cmp::max;
+use core::mem::size_of;
+
+/// Return the largest power of two which is not greater than `num`, or 0 if `num` is 0
+///
+/// This is the size of the largest block that fits in `num` bytes or frames.
+pub const fn prev_power_of_two(num: usize) -> usize {
+    if num == 0 {
+        0
+    } else {
+        1 << (usize::BITS - 1 - num.leading_zeros())
+    }
+}
+
+/// Return the order of the smallest block that holds `size` bytes and is aligned to `align`, with
+/// the default min order of [`Heap`](crate::Heap)
+///
+/// A [`Heap`](crate::Heap) looks for a free block of this order, but only allocates a block of
+/// order `class_of(size, 1)`, as the alignment only decides which free block is split. A size
+/// too large for any block returns `usize::BITS`, which is larger than the order of any block.
+///
+/// # Panics
+///
+/// Panics if `align` is not a power of two.
+pub fn class_of(size: usize, align: usize) -> usize {
+    assert!(align.is_power_of_two());
+    match size.checked_next_power_of_two() {
+        Some(size) => max(max(size, align), size_of::<usize>()).trailing_zeros() as usize,
+        None => usize::BITS as usize,
+    }
+}